@@ -0,0 +1,234 @@
+//! Timetable and stop list queries on the load time indexes against the linear scans they
+//! replaced. Run with `cargo bench`, set `BENCH_FEED` to a GTFS zip to measure a real feed.
+
+use std::hint::black_box;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use chrono::Local;
+
+use spb_arrival_bot::gtfs::{self, RouteId, StaticFeed, StopId};
+
+const GENERATED_ROUTES: usize = 300;
+const GENERATED_STOPS: usize = 3000;
+const STOPS_PER_ROUTE: usize = 30;
+const TRIPS_PER_DIRECTION: usize = 60;
+const QUERIES: usize = 200;
+const RUN_TIME: Duration = Duration::from_secs(2);
+
+/// Stops of the route in the forward direction
+fn generated_route_stops(route: usize) -> Vec<usize> {
+    (0..STOPS_PER_ROUTE)
+        .map(|k| (route * 7 + k * 13) % GENERATED_STOPS)
+        .collect()
+}
+
+/// A feed about the size of the SPb one: every route runs both ways all day, stops are shared
+/// by several routes
+fn generate_feed(path: &Path) {
+    let mut routes = String::from("route_id,route_short_name,route_long_name,route_type\n");
+    let mut stops = String::from("stop_id,stop_name,stop_lat,stop_lon\n");
+    let mut trips = String::from("route_id,service_id,trip_id,direction_id\n");
+    let mut stop_times =
+        String::from("trip_id,arrival_time,departure_time,stop_id,stop_sequence\n");
+
+    for stop in 0..GENERATED_STOPS {
+        let lat = 59.8 + (stop % 60) as f64 * 0.005;
+        let lon = 30.1 + (stop / 60) as f64 * 0.01;
+        stops += &format!("{stop},Stop {stop},{lat},{lon}\n");
+    }
+    for route in 0..GENERATED_ROUTES {
+        routes += &format!("{route},{route},Route {route},3\n");
+        let forward = generated_route_stops(route);
+        let backward = forward.iter().rev().copied().collect::<Vec<_>>();
+        for (direction, route_stops) in [(0, forward), (1, backward)] {
+            for trip in 0..TRIPS_PER_DIRECTION {
+                let trip_id = format!("{route}-{direction}-{trip}");
+                trips += &format!("{route},daily,{trip_id},{direction}\n");
+                for (sequence, stop) in route_stops.iter().enumerate() {
+                    let time = 5 * 3600 + trip * 20 * 60 + sequence * 2 * 60;
+                    let time = format!("{}:{:02}:00", time / 3600, time / 60 % 60);
+                    stop_times += &format!("{trip_id},{time},{time},{stop},{}\n", sequence + 1);
+                }
+            }
+        }
+    }
+
+    let tables = [
+        (
+            "agency.txt",
+            String::from(
+                "agency_name,agency_url,agency_timezone\nBench,http://example.com,Europe/Moscow\n",
+            ),
+        ),
+        ("routes.txt", routes),
+        ("stops.txt", stops),
+        ("trips.txt", trips),
+        ("stop_times.txt", stop_times),
+        (
+            "calendar.txt",
+            String::from(
+                "service_id,monday,tuesday,wednesday,thursday,friday,saturday,sunday,start_date,end_date\n\
+                 daily,1,1,1,1,1,1,1,20200101,20991231\n",
+            ),
+        ),
+    ];
+    let mut zip = zip::ZipWriter::new(std::fs::File::create(path).unwrap());
+    for (name, contents) in tables {
+        zip.start_file(name, zip::write::FileOptions::default())
+            .unwrap();
+        zip.write_all(contents.as_bytes()).unwrap();
+    }
+    zip.finish().unwrap();
+}
+
+/// Stop list as it was taken before the indexes: stops of the first trip with stop times
+fn route_stops_linear(feed: &StaticFeed, route_id: &RouteId, direction: &str) -> Vec<StopId> {
+    let Some(trips) = feed.trips.get(route_id) else {
+        return vec![];
+    };
+    let trip_ids = if direction == "0" {
+        &trips.forward_trip
+    } else {
+        &trips.backward_trip
+    };
+    trip_ids
+        .iter()
+        .find_map(|trip| feed.stop_times.get(trip))
+        .map(|stops| stops.iter().map(|stop| stop.stop_id.clone()).collect())
+        .unwrap_or_default()
+}
+
+/// Timetable as it was built before the indexes: every stop time of every trip of the route
+fn arrivals_linear(
+    feed: &StaticFeed,
+    route_id: &RouteId,
+    direction: &str,
+    stop_id: &StopId,
+    from: i64,
+    until: i64,
+) -> Vec<i64> {
+    let Some(trips) = feed.trips.get(route_id) else {
+        return vec![];
+    };
+    let trip_ids = if direction == "0" {
+        &trips.forward_trip
+    } else {
+        &trips.backward_trip
+    };
+    let first_day = feed.date_of(from).pred_opt().unwrap_or_default();
+    let last_day = feed.date_of(until);
+
+    let mut timetable = vec![];
+    for trip in trip_ids {
+        let Some(trip_info) = feed.stop_times.get(trip) else {
+            continue;
+        };
+        for day in first_day.iter_days().take_while(|day| day <= &last_day) {
+            if !feed.trip_is_active(trip, day) {
+                continue;
+            }
+            for trip_stop in trip_info.iter().filter(|s| &s.stop_id == stop_id) {
+                let timestamp = feed.instant(day, trip_stop.time);
+                if from < timestamp && timestamp < until {
+                    timetable.push(timestamp);
+                }
+            }
+        }
+    }
+    timetable.sort();
+    timetable
+}
+
+/// Mean time of one query, the queries are run round and round for `RUN_TIME`
+fn measure<T>(
+    queries: &[(RouteId, String, StopId)],
+    mut query: impl FnMut(&RouteId, &str, &StopId) -> T,
+) -> Duration {
+    let started = Instant::now();
+    let mut count = 0u32;
+    while started.elapsed() < RUN_TIME {
+        for (route_id, direction, stop_id) in queries {
+            black_box(query(route_id, direction, stop_id));
+        }
+        count += queries.len() as u32;
+    }
+    started.elapsed() / count
+}
+
+fn report(name: &str, linear: Duration, indexed: Duration) {
+    println!(
+        "{name:<12} linear scan {linear:>12.2?}   indexed {indexed:>12.2?}   x{:.1}",
+        linear.as_secs_f64() / indexed.as_secs_f64()
+    );
+}
+
+fn main() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = match std::env::var("BENCH_FEED") {
+        Ok(path) => PathBuf::from(path),
+        Err(_) => {
+            let path = dir.path().join("feed.zip");
+            generate_feed(&path);
+            println!("BENCH_FEED is not set, using a generated feed");
+            path
+        }
+    };
+
+    let started = Instant::now();
+    let feed = gtfs::read_feed_file(&path).unwrap();
+    println!(
+        "Feed of {} routes, {} stops, {} trips parsed and indexed in {:.2?}",
+        feed.routes.all.len(),
+        feed.stops.len(),
+        feed.stop_times.len(),
+        started.elapsed()
+    );
+
+    // A stop in the middle of each route, both directions
+    let mut route_ids = feed.trips.keys().cloned().collect::<Vec<_>>();
+    route_ids.sort();
+    let queries = route_ids
+        .iter()
+        .flat_map(|route_id| ["0", "1"].map(|direction| (route_id, direction)))
+        .filter_map(|(route_id, direction)| {
+            let stops = feed.route_stops(route_id, direction)?;
+            let stop_id = stops.get(stops.len() / 2)?.clone();
+            Some((route_id.clone(), direction.to_string(), stop_id))
+        })
+        .take(QUERIES)
+        .collect::<Vec<_>>();
+
+    let from = Local::now().timestamp();
+    let until = from + 86400;
+    for (route_id, direction, stop_id) in &queries {
+        let indexed = feed
+            .arrivals(route_id, direction, stop_id, from, until)
+            .into_iter()
+            .map(|arrival| arrival.time)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            indexed,
+            arrivals_linear(&feed, route_id, direction, stop_id, from, until),
+            "arrivals of route {route_id} at stop {stop_id} differ"
+        );
+    }
+    println!("{} queries, {:?} each", queries.len(), RUN_TIME);
+
+    let linear = measure(&queries, |route_id, direction, stop_id| {
+        arrivals_linear(&feed, route_id, direction, stop_id, from, until)
+    });
+    let indexed = measure(&queries, |route_id, direction, stop_id| {
+        feed.arrivals(route_id, direction, stop_id, from, until)
+    });
+    report("arrivals", linear, indexed);
+
+    let linear = measure(&queries, |route_id, direction, _| {
+        route_stops_linear(&feed, route_id, direction)
+    });
+    let indexed = measure(&queries, |route_id, direction, _| {
+        feed.route_stops(route_id, direction).cloned()
+    });
+    report("route_stops", linear, indexed);
+}
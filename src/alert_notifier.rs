@@ -0,0 +1,264 @@
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use lazy_static::lazy_static;
+use teloxide::prelude::*;
+use tokio::sync::RwLock;
+
+use crate::gtfs::{RouteId, ServiceAlert, StopId};
+use crate::saved_routes_db;
+use crate::transit::Transit;
+
+const ALERTS_POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+lazy_static! {
+    /// Alerts in effect right now, by city and alert ID
+    static ref ACTIVE_ALERTS: RwLock<HashMap<String, HashMap<String, ServiceAlert>>> =
+        RwLock::new(HashMap::new());
+    /// Databases of the known alerts by city, sled locks each one for the whole process
+    static ref KNOWN_DBS: std::sync::Mutex<HashMap<String, sled::Db>> =
+        std::sync::Mutex::new(HashMap::new());
+}
+
+enum AlertChange {
+    Started(ServiceAlert),
+    Changed(ServiceAlert),
+    Ended(ServiceAlert),
+}
+
+impl AlertChange {
+    fn alert(&self) -> &ServiceAlert {
+        match self {
+            AlertChange::Started(alert)
+            | AlertChange::Changed(alert)
+            | AlertChange::Ended(alert) => alert,
+        }
+    }
+
+    fn text(&self) -> String {
+        let alert = self.alert();
+        match self {
+            AlertChange::Started(_) => format!("⚠️{}\r\n{}", alert.header, alert.description),
+            AlertChange::Changed(_) => {
+                format!("✏️Обновлено: {}\r\n{}", alert.header, alert.description)
+            }
+            AlertChange::Ended(_) => format!("✅Больше не действует: {}", alert.header),
+        }
+    }
+}
+
+/// Active alerts of the city touching the route or the stop
+pub async fn alerts_for(
+    city: &str,
+    route_id: &RouteId,
+    stop_id: Option<&StopId>,
+) -> Vec<ServiceAlert> {
+    let mut alerts = ACTIVE_ALERTS
+        .read()
+        .await
+        .get(city)
+        .into_iter()
+        .flat_map(|alerts| alerts.values())
+        .filter(|alert| alert.affects(route_id, stop_id))
+        .cloned()
+        .collect::<Vec<_>>();
+    alerts.sort_by(|a, b| a.id.cmp(&b.id));
+    alerts
+}
+
+/// Polls service alerts of the city and tells chats with affected saved routes when an alert
+/// starts, changes or ends
+pub async fn run(bot: Bot, transit: Transit) {
+    let city = transit.id().to_string();
+    // Alerts seen before restart, so nobody hears about them twice
+    match load_known(&city) {
+        Ok(known) => {
+            ACTIVE_ALERTS.write().await.insert(city.clone(), known);
+        }
+        Err(e) => log::error!("Failed to load known alerts of {city}: {e}"),
+    }
+
+    loop {
+        match poll(&transit).await {
+            Ok(Some(changes)) => {
+                if let Err(e) = notify(&bot, &city, &changes).await {
+                    log::error!("Failed to notify about alerts of {city}: {e}");
+                }
+            }
+            Ok(None) => {
+                log::warn!("No alerts feed configured for {city}");
+                return;
+            }
+            Err(e) => log::error!("Failed to get service alerts of {city}: {e}"),
+        }
+        tokio::time::sleep(ALERTS_POLL_INTERVAL).await;
+    }
+}
+
+/// Fetches alerts and updates the active ones, `None` if there is no alerts feed
+async fn poll(transit: &Transit) -> Result<Option<Vec<AlertChange>>> {
+    let Some(alerts) = transit.service_alerts().await? else {
+        return Ok(None);
+    };
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let current = alerts
+        .into_iter()
+        .filter(|alert| alert.is_active(now))
+        .map(|alert| (alert.id.clone(), alert))
+        .collect::<HashMap<_, _>>();
+
+    let city = transit.id();
+    let mut all_active = ACTIVE_ALERTS.write().await;
+    let active = all_active.entry(city.to_string()).or_default();
+    let changes = update_active(active, current);
+
+    if !changes.is_empty() {
+        log::warn!(
+            "{} alert changes, {} alerts active in {city}",
+            changes.len(),
+            active.len()
+        );
+        save_known(city, active)?;
+    }
+    Ok(Some(changes))
+}
+
+/// Replaces the active alerts with the current ones and tells what changed
+fn update_active(
+    active: &mut HashMap<String, ServiceAlert>,
+    mut current: HashMap<String, ServiceAlert>,
+) -> Vec<AlertChange> {
+    let mut changes = vec![];
+    for (id, alert) in &current {
+        match active.get(id) {
+            None => changes.push(AlertChange::Started(alert.clone())),
+            Some(old) if old != alert => changes.push(AlertChange::Changed(alert.clone())),
+            Some(_) => {}
+        }
+    }
+    for (id, old) in active.drain() {
+        if !current.contains_key(&id) {
+            changes.push(AlertChange::Ended(old));
+        }
+    }
+    active.extend(current.drain());
+    changes
+}
+
+async fn notify(bot: &Bot, city: &str, changes: &[AlertChange]) -> Result<()> {
+    if changes.is_empty() {
+        return Ok(());
+    }
+
+    for (chat_id, routes) in saved_routes_db::all_saved_routes()? {
+        for change in changes {
+            let alert = change.alert();
+            let mut affected = routes
+                .iter()
+                .filter(|(_, data)| data.city == city)
+                .filter(|(_, data)| {
+                    data.routes
+                        .iter()
+                        .any(|route| alert.affects(&route.route_id, Some(&data.stop_id)))
+                })
+                .map(|(name, _)| name.as_str())
+                .collect::<Vec<&str>>();
+            if affected.is_empty() {
+                continue;
+            }
+            affected.sort();
+
+            let text = format!("{}\r\n🔖{}", change.text(), affected.join(", "));
+            // Chats that blocked the bot shouldn't stop the others from hearing
+            if let Err(e) = bot.send_message(chat_id, text).await {
+                log::warn!(
+                    "Failed to send alert {} to chat ID {}: {e}",
+                    alert.id,
+                    chat_id.0
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+fn known_db(city: &str) -> Result<sled::Db> {
+    let mut dbs = KNOWN_DBS.lock().unwrap();
+    if let Some(db) = dbs.get(city) {
+        return Ok(db.clone());
+    }
+    let db = sled::Config::new()
+        .path(format!("db/alerts_{city}"))
+        .open()?;
+    dbs.insert(city.to_string(), db.clone());
+    Ok(db)
+}
+
+fn load_known(city: &str) -> Result<HashMap<String, ServiceAlert>> {
+    let db = known_db(city)?;
+    let mut known = HashMap::new();
+    for entry in db.iter() {
+        let (_, value) = entry?;
+        let alert = bincode::deserialize::<ServiceAlert>(&value)?;
+        known.insert(alert.id.clone(), alert);
+    }
+    Ok(known)
+}
+
+fn save_known(city: &str, alerts: &HashMap<String, ServiceAlert>) -> Result<()> {
+    let db = known_db(city)?;
+    db.clear()?;
+    for (id, alert) in alerts {
+        db.insert(id.as_bytes(), bincode::serialize(alert)?)?;
+    }
+    db.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn alert(id: &str, header: &str) -> (String, ServiceAlert) {
+        let alert = ServiceAlert {
+            id: id.to_string(),
+            header: header.to_string(),
+            description: String::new(),
+            informed: Default::default(),
+            active_periods: vec![],
+        };
+        (alert.id.clone(), alert)
+    }
+
+    fn summary(changes: &[AlertChange]) -> Vec<String> {
+        let mut summary = changes
+            .iter()
+            .map(|change| match change {
+                AlertChange::Started(alert) => format!("started {}", alert.id),
+                AlertChange::Changed(alert) => format!("changed {}", alert.id),
+                AlertChange::Ended(alert) => format!("ended {}", alert.id),
+            })
+            .collect::<Vec<_>>();
+        summary.sort();
+        summary
+    }
+
+    #[test]
+    fn alerts_start_change_and_end() {
+        let mut active = HashMap::from([alert("1", "a"), alert("2", "b"), alert("3", "c")]);
+        let current = HashMap::from([alert("1", "a"), alert("2", "b2"), alert("4", "d")]);
+        let changes = update_active(&mut active, current.clone());
+        assert_eq!(summary(&changes), ["changed 2", "ended 3", "started 4"]);
+        assert_eq!(active, current);
+    }
+
+    #[test]
+    fn same_alerts_again_are_no_change() {
+        let mut active = HashMap::from([alert("1", "a"), alert("2", "b")]);
+        let current = active.clone();
+        let changes = update_active(&mut active, current);
+        assert!(changes.is_empty());
+        assert_eq!(active.len(), 2);
+    }
+}
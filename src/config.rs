@@ -0,0 +1,328 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use chrono::NaiveDate;
+use chrono_tz::Tz;
+use lazy_static::lazy_static;
+
+lazy_static! {
+    static ref LOADED: Result<Config> = Config::from_env();
+    pub static ref CONFIG: &'static Config = LOADED
+        .as_ref()
+        .expect("configuration is checked by load() at startup");
+}
+
+const DEFAULT_CITY_ID: &str = "spb";
+const DEFAULT_CITY_NAME: &str = "Санкт-Петербург";
+const DEFAULT_STATIC_FEED: &str =
+    "https://transport.orgp.spb.ru/Portal/transport/internalapi/gtfs/feed.zip";
+const DEFAULT_STOP_FORECAST: &str = "https://transport.orgp.spb.ru/Portal/transport/internalapi/gtfs/realtime/stopforecast?stopID={stop_id}";
+const DEFAULT_VEHICLE_POSITIONS: &str = "https://transport.orgp.spb.ru/Portal/transport/internalapi/gtfs/realtime/vehicle?routeIDs={route_id}";
+const DEFAULT_REALTIME_MAX_AGE: Duration = Duration::from_secs(180);
+/// Public holidays of Russia falling on the same day every year, as month and day
+const RUSSIAN_HOLIDAYS: [(u32, u32); 14] = [
+    (1, 1),
+    (1, 2),
+    (1, 3),
+    (1, 4),
+    (1, 5),
+    (1, 6),
+    (1, 7),
+    (1, 8),
+    (2, 23),
+    (3, 8),
+    (5, 1),
+    (5, 9),
+    (6, 12),
+    (11, 4),
+];
+
+/// Reads the configuration from the environment. Called at startup, so a malformed variable
+/// stops the bot before anything uses `CONFIG`.
+pub fn load() -> Result<&'static Config, &'static anyhow::Error> {
+    LOADED.as_ref()
+}
+
+/// Where to take the GTFS archive from
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+#[serde(from = "String")]
+pub enum FeedLocation {
+    Url(String),
+    File(PathBuf),
+}
+
+impl From<String> for FeedLocation {
+    fn from(s: String) -> Self {
+        Self::from(s.as_str())
+    }
+}
+
+impl From<&str> for FeedLocation {
+    /// Accepts `http(s)://` URLs, `file://` URLs and plain paths
+    fn from(s: &str) -> Self {
+        if let Some(path) = s.strip_prefix("file://") {
+            Self::File(PathBuf::from(path))
+        } else if s.starts_with("http://") || s.starts_with("https://") {
+            Self::Url(s.to_string())
+        } else {
+            Self::File(PathBuf::from(s))
+        }
+    }
+}
+
+/// A city with its own feeds. Listed in the `CITIES` file, or made of `STATIC_FEED`,
+/// `STOP_FORECAST_URL`, `VEHICLE_POSITIONS_URL` and `ALERTS_URL` when there is none.
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct CityConfig {
+    /// Short ID stored with chats and saved routes, never change it
+    pub id: String,
+    /// Shown to users
+    pub name: String,
+    /// GTFS zip URL or path
+    pub static_feed: FeedLocation,
+    /// GTFS-RT trip updates for a stop, `{stop_id}` is replaced with the stop ID
+    pub stop_forecast_url: Option<String>,
+    /// GTFS-RT vehicle positions of a route, `{route_id}` is replaced with the route ID
+    pub vehicle_positions_url: Option<String>,
+    /// GTFS-RT service alerts
+    pub alerts_url: Option<String>,
+    /// Overrides the timezone of the feed's agencies
+    pub timezone: Option<Tz>,
+    /// Public holidays falling on the same day every year as `[month, day]`, Russian by default
+    #[serde(default = "russian_holidays")]
+    pub fixed_holidays: Vec<(u32, u32)>,
+}
+
+fn russian_holidays() -> Vec<(u32, u32)> {
+    RUSSIAN_HOLIDAYS.to_vec()
+}
+
+impl CityConfig {
+    fn from_env() -> Result<Self> {
+        let static_feed = std::env::var("STATIC_FEED")
+            .map(|s| FeedLocation::from(s.as_str()))
+            .unwrap_or_else(|_| FeedLocation::Url(DEFAULT_STATIC_FEED.to_string()));
+
+        let stop_forecast_url = std::env::var("STOP_FORECAST_URL")
+            .unwrap_or_else(|_| DEFAULT_STOP_FORECAST.to_string());
+        check_template("STOP_FORECAST_URL", &stop_forecast_url, "{stop_id}")?;
+
+        let vehicle_positions_url = std::env::var("VEHICLE_POSITIONS_URL")
+            .unwrap_or_else(|_| DEFAULT_VEHICLE_POSITIONS.to_string());
+        check_template(
+            "VEHICLE_POSITIONS_URL",
+            &vehicle_positions_url,
+            "{route_id}",
+        )?;
+
+        let alerts_url = std::env::var("ALERTS_URL").ok();
+
+        Ok(Self {
+            id: DEFAULT_CITY_ID.to_string(),
+            name: DEFAULT_CITY_NAME.to_string(),
+            static_feed,
+            stop_forecast_url: Some(stop_forecast_url),
+            vehicle_positions_url: Some(vehicle_positions_url),
+            alerts_url,
+            timezone: None,
+            fixed_holidays: russian_holidays(),
+        })
+    }
+
+    fn check(&self) -> Result<()> {
+        if let Some(template) = &self.stop_forecast_url {
+            check_template("stop_forecast_url", template, "{stop_id}")?;
+        }
+        if let Some(template) = &self.vehicle_positions_url {
+            check_template("vehicle_positions_url", template, "{route_id}")?;
+        }
+        Ok(())
+    }
+
+    pub fn stop_forecast_url(&self, stop_id: &str) -> Option<String> {
+        let template = self.stop_forecast_url.as_ref()?;
+        Some(template.replace("{stop_id}", stop_id))
+    }
+
+    pub fn vehicle_positions_url(&self, route_id: &str) -> Option<String> {
+        let template = self.vehicle_positions_url.as_ref()?;
+        Some(template.replace("{route_id}", route_id))
+    }
+}
+
+/// Cities of the `CITIES` file, every one checked
+fn parse_cities(yaml: &str) -> Result<Vec<CityConfig>> {
+    let cities = serde_yaml::from_str::<Vec<CityConfig>>(yaml)?;
+    if cities.is_empty() {
+        return Err(anyhow!("no cities listed"));
+    }
+    for (i, city) in cities.iter().enumerate() {
+        city.check().with_context(|| format!("city {}", city.id))?;
+        if cities[..i].iter().any(|other| other.id == city.id) {
+            return Err(anyhow!("city {} is listed twice", city.id));
+        }
+    }
+    Ok(cities)
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// `CITIES`: YAML file with a list of cities, see `CityConfig`. The first one is the default.
+    pub cities: Vec<CityConfig>,
+    /// `REALTIME_MAX_AGE_SEC`: realtime predictions older than this are ignored
+    pub realtime_max_age: Duration,
+    /// `TRANSIT_FIXTURE`: directory with a feed and recorded realtime data to run on offline
+    pub transit_fixture: Option<PathBuf>,
+    /// `FEED_UPDATE_INTERVAL_MIN`: how often to reload the static feed besides the nightly update
+    pub feed_update_interval: Option<Duration>,
+    /// `HOLIDAYS`: YAML file with a list of days off in every city besides the fixed holidays
+    pub holidays: Vec<NaiveDate>,
+}
+
+impl Config {
+    fn from_env() -> Result<Self> {
+        let cities = match std::env::var("CITIES") {
+            Ok(path) => std::fs::read_to_string(&path)
+                .map_err(anyhow::Error::from)
+                .and_then(|yaml| parse_cities(&yaml))
+                .with_context(|| format!("CITIES {path}"))?,
+            Err(_) => vec![CityConfig::from_env()?],
+        };
+
+        let realtime_max_age = std::env::var("REALTIME_MAX_AGE_SEC")
+            .ok()
+            .and_then(|sec| match sec.parse::<u64>() {
+                Ok(sec) => Some(Duration::from_secs(sec)),
+                Err(_) => {
+                    log::error!("Invalid REALTIME_MAX_AGE_SEC {sec}, using the default");
+                    None
+                }
+            })
+            .unwrap_or(DEFAULT_REALTIME_MAX_AGE);
+
+        let transit_fixture = std::env::var("TRANSIT_FIXTURE").ok().map(PathBuf::from);
+
+        let feed_update_interval = std::env::var("FEED_UPDATE_INTERVAL_MIN")
+            .ok()
+            .and_then(|min| match min.parse::<u64>() {
+                Ok(min) if min > 0 => Some(Duration::from_secs(min * 60)),
+                _ => {
+                    log::error!(
+                        "Invalid FEED_UPDATE_INTERVAL_MIN {min}, only nightly updates are enabled"
+                    );
+                    None
+                }
+            });
+
+        let holidays = match std::env::var("HOLIDAYS") {
+            Ok(path) => std::fs::read_to_string(&path)
+                .map_err(anyhow::Error::from)
+                .and_then(|yaml| parse_holidays(&yaml))
+                .with_context(|| format!("HOLIDAYS {path}"))?,
+            Err(_) => vec![],
+        };
+
+        let config = Self {
+            cities,
+            realtime_max_age,
+            transit_fixture,
+            feed_update_interval,
+            holidays,
+        };
+        log::warn!("{config:#?}");
+        Ok(config)
+    }
+
+    /// ID of the city chats and routes stored before there were several
+    pub fn default_city(&self) -> &str {
+        &self.cities[0].id
+    }
+
+    pub fn city(&self, id: &str) -> Option<&CityConfig> {
+        self.cities.iter().find(|city| city.id == id)
+    }
+}
+
+fn parse_holidays(yaml: &str) -> Result<Vec<NaiveDate>> {
+    Ok(serde_yaml::from_str(yaml)?)
+}
+
+/// URL templates without the placeholder would ask the same URL for every stop or route
+fn check_template(name: &str, template: &str, placeholder: &str) -> Result<()> {
+    if template.contains(placeholder) {
+        Ok(())
+    } else {
+        Err(anyhow!("{name} has no {placeholder} placeholder"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cities_listed() {
+        let cities = parse_cities(
+            "- id: spb\n  name: Санкт-Петербург\n  static_feed: https://example.com/spb.zip\n  \
+             stop_forecast_url: https://example.com/stop?id={stop_id}\n\
+             - id: msk\n  name: Москва\n  static_feed: /data/msk.zip\n  timezone: Europe/Moscow\n",
+        )
+        .unwrap();
+        assert_eq!(cities.len(), 2);
+        assert_eq!(
+            cities[0].stop_forecast_url("7").as_deref(),
+            Some("https://example.com/stop?id=7")
+        );
+        assert_eq!(
+            cities[1].static_feed,
+            FeedLocation::File(PathBuf::from("/data/msk.zip"))
+        );
+        assert_eq!(cities[1].vehicle_positions_url("7"), None);
+    }
+
+    #[test]
+    fn malformed_cities_rejected() {
+        assert!(parse_cities("[]").is_err());
+        assert!(parse_cities("- id: spb\n").is_err());
+
+        let error = parse_cities(
+            "- id: msk\n  name: Москва\n  static_feed: /data/msk.zip\n  \
+             vehicle_positions_url: https://example.com/vehicles\n",
+        )
+        .unwrap_err();
+        assert_eq!(
+            format!("{error:#}"),
+            "city msk: vehicle_positions_url has no {route_id} placeholder"
+        );
+
+        let city = "- id: spb\n  name: SPb\n  static_feed: /data/spb.zip\n";
+        let error = parse_cities(&city.repeat(2)).unwrap_err();
+        assert_eq!(error.to_string(), "city spb is listed twice");
+    }
+
+    #[test]
+    fn fixed_holidays_by_city() {
+        let cities = parse_cities(
+            "- id: spb\n  name: SPb\n  static_feed: /data/spb.zip\n\
+             - id: ber\n  name: Berlin\n  static_feed: /data/ber.zip\n  \
+             fixed_holidays: [[1, 1], [10, 3]]\n",
+        )
+        .unwrap();
+        assert!(cities[0].fixed_holidays.contains(&(5, 9)));
+        assert_eq!(cities[1].fixed_holidays, vec![(1, 1), (10, 3)]);
+    }
+
+    #[test]
+    fn holidays_listed() {
+        assert_eq!(
+            parse_holidays("- 2026-01-09\n- 2026-05-11\n").unwrap(),
+            vec![
+                NaiveDate::from_ymd_opt(2026, 1, 9).unwrap(),
+                NaiveDate::from_ymd_opt(2026, 5, 11).unwrap()
+            ]
+        );
+        assert!(parse_holidays("- 2026-13-01\n").is_err());
+        assert!(parse_holidays("next friday").is_err());
+    }
+}
@@ -0,0 +1,76 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+
+use crate::gtfs::StaticFeed;
+
+fn cache_path(city: &str) -> PathBuf {
+    PathBuf::from(format!("db/static_feed_{city}.bin"))
+}
+
+/// Bump whenever `StaticFeed` layout changes, so an old snapshot is not misread
+const FORMAT_VERSION: u32 = 1;
+
+/// Loads the city's feed snapshot saved by the previous run
+pub fn load(city: &str) -> Result<StaticFeed> {
+    load_from(&cache_path(city))
+}
+
+/// Saves the feed snapshot. The previous one is replaced only when the new one is completely written.
+pub fn save(city: &str, feed: &StaticFeed) -> Result<()> {
+    save_to(&cache_path(city), feed)
+}
+
+fn load_from(path: &Path) -> Result<StaticFeed> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let version: u32 = bincode::deserialize_from(&mut reader)?;
+    if version != FORMAT_VERSION {
+        return Err(anyhow!(
+            "Feed cache format {version} is outdated, expected {FORMAT_VERSION}"
+        ));
+    }
+    let feed = bincode::deserialize_from(&mut reader)?;
+    Ok(feed)
+}
+
+fn save_to(path: &Path, feed: &StaticFeed) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let tmp_path = path.with_extension("bin.tmp");
+    {
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        bincode::serialize_into(&mut writer, &FORMAT_VERSION)?;
+        bincode::serialize_into(&mut writer, feed)?;
+        writer.into_inner()?.sync_all()?;
+    }
+    std::fs::rename(tmp_path, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gtfs::test_feed;
+
+    #[test]
+    fn other_format_versions_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("static_feed.bin");
+        let feed = test_feed::sample();
+        save_to(&path, &feed).unwrap();
+        assert_eq!(load_from(&path).unwrap().stops.len(), feed.stops.len());
+
+        // A snapshot written by another version, its feed must not be read at all
+        let mut snapshot = std::fs::read(&path).unwrap();
+        snapshot[..4].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        std::fs::write(&path, snapshot).unwrap();
+        assert!(load_from(&path).is_err());
+
+        // The feed downloaded instead replaces it
+        save_to(&path, &feed).unwrap();
+        assert_eq!(load_from(&path).unwrap().stops.len(), feed.stops.len());
+    }
+}
@@ -0,0 +1,87 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use chrono::{NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+
+use crate::config::CONFIG;
+use crate::feed_cache;
+use crate::gtfs;
+use crate::transit::{CityTransit, TransitProvider};
+
+/// Hour of the nightly update in the feed's timezone, when nobody is waiting for a tram
+const NIGHTLY_UPDATE_HOUR: u32 = 3;
+
+/// Delay after the first failed startup load, doubled after each next failure up to `MAX_RETRY_DELAY`
+const FIRST_RETRY_DELAY: Duration = Duration::from_secs(5);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(10 * 60);
+
+/// Keeps the city's feed fresh: reloads it every night and, if configured, more often.
+pub async fn run(city: Arc<CityTransit>) {
+    loop {
+        let timezone = city.feed().await.timezone();
+        let mut delay = until_nightly_update(timezone);
+        if let Some(interval) = CONFIG.feed_update_interval {
+            delay = delay.min(interval);
+        }
+        tokio::time::sleep(delay).await;
+
+        if let Err(e) = update(&city).await {
+            log::error!(
+                "Failed to update static feed of {}, keeping the old one: {e}",
+                city.id()
+            );
+        }
+    }
+}
+
+/// Loads the city's feed at startup. The city can't answer anything without it, so this
+/// retries until the feed is there.
+pub async fn first_update(city: &CityTransit) {
+    let mut delay = FIRST_RETRY_DELAY;
+    while let Err(e) = update(city).await {
+        log::error!(
+            "Failed to load static feed of {}, retrying in {delay:?}: {e}",
+            city.id()
+        );
+        tokio::time::sleep(delay).await;
+        delay = (delay * 2).min(MAX_RETRY_DELAY);
+    }
+}
+
+/// Downloads and parses a new feed and swaps it in. Old feed keeps serving if anything goes wrong.
+pub async fn update(city: &CityTransit) -> Result<()> {
+    log::warn!("Updating static feed of {}", city.id());
+    let source = city.feed().await.source.clone();
+    let Some(feed) = gtfs::static_feed(&city.config.static_feed, &source).await? else {
+        return Ok(());
+    };
+
+    let feed = Arc::new(feed);
+    let snapshot = feed.clone();
+    let id = city.id().to_string();
+    let saved = tokio::task::spawn_blocking(move || feed_cache::save(&id, &snapshot)).await?;
+    if let Err(e) = saved {
+        log::error!("Failed to save static feed cache: {e}");
+    }
+
+    let feed = Arc::try_unwrap(feed).map_err(|_| anyhow!("Static feed is still in use"))?;
+    city.set_feed(feed).await;
+    log::warn!("Static feed of {} updated", city.id());
+    Ok(())
+}
+
+fn until_nightly_update(timezone: Tz) -> Duration {
+    let now = Utc::now().with_timezone(&timezone);
+    let time = NaiveTime::from_hms_opt(NIGHTLY_UPDATE_HOUR, 0, 0).unwrap();
+    let mut next = now.date_naive().and_time(time);
+    if next <= now.naive_local() {
+        next += chrono::Duration::days(1);
+    }
+    // The hour may be skipped by a DST switch, then it's counted as if there were no switch
+    match timezone.from_local_datetime(&next).earliest() {
+        Some(next) => (next - now).to_std().unwrap_or_default(),
+        None => (next - now.naive_local()).to_std().unwrap_or_default(),
+    }
+}
@@ -0,0 +1,157 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use gtfs_rt::FeedMessage;
+use lazy_static::lazy_static;
+use tokio::sync::{broadcast, Mutex};
+
+use crate::config::CONFIG;
+use crate::gtfs::{self, StopId};
+use crate::transit::Transit;
+
+/// How often a stop with someone waiting at it is polled
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+/// How long a fetched forecast is served without asking again
+const FORECAST_TTL: Duration = Duration::from_secs(4);
+/// Updates a slow subscriber may fall behind before skipping to the latest
+const CHANNEL_CAPACITY: usize = 4;
+
+/// Stop IDs are only unique within a city
+type CityStop = (String, StopId);
+
+#[derive(Debug, Clone)]
+pub enum StopUpdate {
+    Forecast(Arc<FeedMessage>),
+    /// The realtime endpoint didn't answer, the timetable is all there is
+    Unavailable,
+}
+
+lazy_static! {
+    /// Stops being polled, with the channel their forecasts are published to
+    static ref STOPS: Mutex<HashMap<CityStop, broadcast::Sender<StopUpdate>>> =
+        Mutex::new(HashMap::new());
+    static ref CACHE: Mutex<HashMap<CityStop, (Instant, Arc<FeedMessage>)>> =
+        Mutex::new(HashMap::new());
+}
+
+/// Forecast for the stop, fetched at most once per TTL whoever asks
+pub async fn stop_forecast(transit: &Transit, stop_id: &StopId) -> Result<Arc<FeedMessage>> {
+    let key = (transit.id().to_string(), stop_id.clone());
+    if let Some(message) = cached(&key).await {
+        return Ok(message);
+    }
+
+    let message = Arc::new(transit.fetch_stop_forecast(stop_id).await?);
+
+    let mut cache = CACHE.lock().await;
+    cache.retain(|_, (fetched, _)| fetched.elapsed() < FORECAST_TTL);
+    cache.insert(key, (Instant::now(), message.clone()));
+    Ok(message)
+}
+
+async fn cached(key: &CityStop) -> Option<Arc<FeedMessage>> {
+    CACHE
+        .lock()
+        .await
+        .get(key)
+        .filter(|(fetched, _)| fetched.elapsed() < FORECAST_TTL)
+        .map(|(_, message)| message.clone())
+}
+
+/// Forecasts for the stop as they are polled, and the latest one if it's still fresh.
+/// The stop is polled for as long as somebody holds a receiver.
+pub async fn subscribe(
+    transit: &Transit,
+    stop_id: &StopId,
+) -> (Option<StopUpdate>, broadcast::Receiver<StopUpdate>) {
+    let key = (transit.id().to_string(), stop_id.clone());
+    let receiver = {
+        let mut stops = STOPS.lock().await;
+        match stops.get(&key) {
+            Some(sender) => sender.subscribe(),
+            None => {
+                let (sender, receiver) = broadcast::channel(CHANNEL_CAPACITY);
+                stops.insert(key.clone(), sender.clone());
+                tokio::spawn(poll_stop(transit.clone(), stop_id.clone(), sender));
+                log::warn!("Started polling stop {stop_id}");
+                receiver
+            }
+        }
+    };
+
+    (cached(&key).await.map(StopUpdate::Forecast), receiver)
+}
+
+async fn poll_stop(transit: Transit, stop_id: StopId, sender: broadcast::Sender<StopUpdate>) {
+    // The age is logged at debug level on every poll, going stale and fresh again stand out
+    let mut stale = false;
+    loop {
+        {
+            // Subscribing takes the same lock, so nobody joins a stop that's being dropped
+            let mut stops = STOPS.lock().await;
+            if sender.receiver_count() == 0 {
+                stops.remove(&(transit.id().to_string(), stop_id.clone()));
+                log::warn!("Stopped polling stop {stop_id}");
+                return;
+            }
+        }
+
+        let update = match stop_forecast(&transit, &stop_id).await {
+            Ok(message) => {
+                if let Some(age) = gtfs::feed_age(&message) {
+                    log::debug!(
+                        "realtime_feed_age_seconds{{city=\"{}\",stop_id=\"{stop_id}\"}} {age}",
+                        transit.id()
+                    );
+                    let now_stale = age > CONFIG.realtime_max_age.as_secs();
+                    if now_stale && !stale {
+                        log::error!("Forecast for stop {stop_id} is stale, {age}s old");
+                    } else if !now_stale && stale {
+                        log::warn!("Forecast for stop {stop_id} is fresh again, {age}s old");
+                    }
+                    stale = now_stale;
+                }
+                StopUpdate::Forecast(message)
+            }
+            Err(e) => {
+                log::error!("Failed to get forecast for stop {stop_id}: {e}");
+                StopUpdate::Unavailable
+            }
+        };
+        let _ = sender.send(update);
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gtfs::test_feed;
+    use crate::transit::FixtureTransit;
+
+    #[tokio::test(start_paused = true)]
+    async fn chats_at_a_stop_share_one_poll() {
+        let transit: Transit = Arc::new(FixtureTransit::new(test_feed::sample()));
+        let stop_id = String::from("B");
+        let key = (transit.id().to_string(), stop_id.clone());
+
+        let (_, mut first) = subscribe(&transit, &stop_id).await;
+        let (_, mut second) = subscribe(&transit, &stop_id).await;
+        assert_eq!(STOPS.lock().await[&key].receiver_count(), 2);
+
+        let (StopUpdate::Forecast(a), StopUpdate::Forecast(b)) =
+            (first.recv().await.unwrap(), second.recv().await.unwrap())
+        else {
+            panic!("No forecast for the stop");
+        };
+        assert!(Arc::ptr_eq(&a, &b));
+
+        // The poll notices nobody is waiting anymore on its next round
+        drop(first);
+        drop(second);
+        tokio::time::sleep(POLL_INTERVAL * 2).await;
+        assert!(!STOPS.lock().await.contains_key(&key));
+    }
+}
@@ -1,23 +1,66 @@
-use std::collections::HashMap;
+mod alerts;
+mod forecast;
+mod index;
+pub mod realtime;
+mod records;
+mod service_day;
+mod spatial;
+mod stop_search;
+#[cfg(test)]
+pub mod test_feed;
+mod vehicles;
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ffi::OsStr;
 use std::fmt::Display;
-use std::io::{Read, Write};
+use std::io::{Read, Seek, Write};
+use std::path::Path;
 use std::str::FromStr;
 use std::time::SystemTime;
 
-use anyhow::{anyhow, Result};
-use chrono::Local;
-use convert_case::{Case, Casing};
+use anyhow::Result;
+use chrono::{DateTime, Datelike, NaiveDate, Utc};
+use chrono_tz::Tz;
 use gtfs_rt::FeedMessage;
-use prost::Message;
-use tempfile::Builder;
-
-use crate::STATIC_FEED;
+use lazy_static::lazy_static;
+use reqwest::{header, StatusCode};
+use tempfile::{Builder, NamedTempFile};
+
+use crate::config::FeedLocation;
+pub use alerts::{InformedEntity, ServiceAlert};
+pub use forecast::{Cancellation, Forecast};
+use index::{RoutePatterns, StopVisit};
+pub use service_day::ScheduledArrival;
+pub use spatial::Coordinates;
+use spatial::StopIndex;
+pub use vehicles::VehicleOnRoute;
+
+use records::{
+    parse_time, read_records, AgencyRecord, CalendarDateRecord, CalendarRecord, FeedInfoRecord,
+    GtfsRecord, ParseWarning, RouteRecord, StopRecord, StopTimeRecord, TripRecord,
+};
+
+lazy_static! {
+    /// The feed is downloaded every night, connections are kept between the downloads
+    static ref FEED_CLIENT: reqwest::Client = reqwest::Client::new();
+}
 
-#[derive(Debug, PartialEq)]
+/// Order of variants is the order of buttons in the route selection keyboard
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, serde::Serialize, serde::Deserialize,
+)]
 pub enum Vehicle {
     Bus,
-    Tram,
     Trolley,
+    Tram,
+    Metro,
+    Rail,
+    Ferry,
+    WaterBus,
+    CableTram,
+    AerialLift,
+    Funicular,
+    Monorail,
 }
 
 impl Display for Vehicle {
@@ -30,11 +73,39 @@ impl Display for Vehicle {
             Self::Bus => {
                 write!(f, "Автобус 🚌")
             }
+            Self::Metro => write!(f, "Метро 🚇"),
+            Self::Rail => write!(f, "Электричка 🚆"),
+            Self::Ferry => write!(f, "Паром ⛴️"),
+            Self::WaterBus => write!(f, "Водный автобус 🛥️"),
+            Self::CableTram => write!(f, "Канатный трамвай 🚃"),
+            Self::AerialLift => write!(f, "Канатная дорога 🚠"),
+            Self::Funicular => write!(f, "Фуникулёр 🚞"),
+            Self::Monorail => write!(f, "Монорельс 🚝"),
+        }
+    }
+}
+
+impl Vehicle {
+    /// Standard and extended GTFS `route_type` values
+    pub fn from_route_type(route_type: u16) -> Option<Self> {
+        match route_type {
+            0 | 900..=999 => Some(Self::Tram),
+            1 | 400..=404 | 500..=599 => Some(Self::Metro),
+            2 | 100..=199 | 300..=399 => Some(Self::Rail),
+            3 | 200..=299 | 700..=799 => Some(Self::Bus),
+            4 | 1000..=1099 | 1200..=1299 => Some(Self::Ferry),
+            5 => Some(Self::CableTram),
+            6 | 1300..=1399 => Some(Self::AerialLift),
+            7 | 1400..=1499 => Some(Self::Funicular),
+            11 | 800..=899 => Some(Self::Trolley),
+            12 | 405 => Some(Self::Monorail),
+            _ => None,
         }
     }
 }
 
 pub struct ParseVehicleErr;
+/// Parses `transport_type` values of the SPb feed
 impl FromStr for Vehicle {
     type Err = ParseVehicleErr;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
@@ -42,6 +113,10 @@ impl FromStr for Vehicle {
             "bus" => Ok(Self::Bus),
             "tram" => Ok(Self::Tram),
             "trolley" => Ok(Self::Trolley),
+            "metro" | "subway" => Ok(Self::Metro),
+            "train" | "rail" | "suburban" => Ok(Self::Rail),
+            "ferry" => Ok(Self::Ferry),
+            "ship" | "boat" | "waterbus" => Ok(Self::WaterBus),
             _ => Err(ParseVehicleErr),
         }
     }
@@ -57,331 +132,533 @@ pub struct RouteInfo {
     pub name: RouteName,
 }
 
-pub type Tram = HashMap<RouteNumber, RouteInfo>;
-pub type Trolley = HashMap<RouteNumber, RouteInfo>;
-pub type Bus = HashMap<RouteNumber, RouteInfo>;
+pub type RoutesByNumber = HashMap<RouteNumber, RouteInfo>;
 
 #[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
 pub struct RoutesFeed {
-    pub tram: Tram,
-    pub trolley: Trolley,
-    pub bus: Bus,
+    pub by_vehicle: BTreeMap<Vehicle, RoutesByNumber>,
     pub all: HashMap<RouteId, RouteName>,
+    pub numbers: HashMap<RouteId, (Vehicle, RouteNumber)>,
 }
 
 pub type StopId = String;
 pub type StopName = String;
-pub type StopsFeed = HashMap<StopId, StopName>;
+
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
+pub struct StopInfo {
+    pub name: StopName,
+    pub coordinates: Option<Coordinates>,
+}
+
+pub type StopsFeed = HashMap<StopId, StopInfo>;
+
+/// Route passing a stop in the given direction
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct RouteDirection {
+    pub route_id: RouteId,
+    pub direction: String,
+}
 
 pub type TripId = String;
 
 #[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
 pub struct Trips {
-    forward_trip: Vec<TripId>,
-    backward_trip: Vec<TripId>,
+    pub forward_trip: Vec<TripId>,
+    pub backward_trip: Vec<TripId>,
 }
 pub type TripsFeed = HashMap<RouteId, Trips>;
 
 #[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
 pub struct TripStop {
-    timestamp: i64,
-    stop_id: StopId,
-    stop_sequence: u8,
+    /// Seconds since "noon minus 12h" of the service day, may exceed 24h
+    pub time: u32,
+    pub stop_id: StopId,
+    pub stop_sequence: u32,
 }
 
 pub type TripInfo = HashMap<TripId, Vec<TripStop>>;
 
+pub type ServiceId = String;
+
+/// Days of operation of a service, taken from `calendar.txt` and `calendar_dates.txt`
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Service {
+    /// Monday first
+    weekdays: [bool; 7],
+    start_date: Option<NaiveDate>,
+    end_date: Option<NaiveDate>,
+    added: HashSet<NaiveDate>,
+    removed: HashSet<NaiveDate>,
+}
+
+impl Service {
+    pub fn is_active(&self, date: NaiveDate) -> bool {
+        if self.added.contains(&date) {
+            return true;
+        }
+        if self.removed.contains(&date) {
+            return false;
+        }
+        match (self.start_date, self.end_date) {
+            (Some(start), Some(end)) => {
+                start <= date
+                    && date <= end
+                    && self.weekdays[date.weekday().num_days_from_monday() as usize]
+            }
+            _ => false,
+        }
+    }
+}
+
+pub type ServicesFeed = HashMap<ServiceId, Service>;
+
 #[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
 pub struct StaticFeed {
     pub routes: RoutesFeed,
     pub stops: StopsFeed,
     pub trips: TripsFeed,
     pub stop_times: TripInfo,
+    pub services: ServicesFeed,
+    pub trip_services: HashMap<TripId, ServiceId>,
+    /// Timezone of the agency, all stop times are given in it
+    pub timezone: Option<Tz>,
+    pub source: FeedSource,
+    pub stop_index: StopIndex,
+    pub stop_routes: HashMap<StopId, Vec<RouteDirection>>,
+    /// Stops by their normalized names, the IDs are sorted
+    pub stop_names: HashMap<String, Vec<StopId>>,
+    pub trip_routes: HashMap<TripId, RouteDirection>,
+    /// Trips calling at the stop by route and direction, sorted by time
+    pub stop_visits: HashMap<StopId, HashMap<RouteDirection, Vec<StopVisit>>>,
+    pub route_patterns: HashMap<RouteId, RoutePatterns>,
 }
 
-pub async fn static_feed() -> Result<StaticFeed> {
-    let mut feed = StaticFeed::default();
+impl StaticFeed {
+    /// Feeds without calendars are treated as running every day
+    pub fn trip_is_active(&self, trip_id: &TripId, date: NaiveDate) -> bool {
+        if self.services.is_empty() {
+            return true;
+        }
+        self.trip_services
+            .get(trip_id)
+            .and_then(|service_id| self.services.get(service_id))
+            .is_some_and(|service| service.is_active(date))
+    }
+}
+
+/// Where the loaded feed came from. Used to skip downloading and parsing the same feed twice.
+#[derive(Debug, Default, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct FeedSource {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    /// `feed_version` from `feed_info.txt`
+    pub version: Option<String>,
+}
+
+/// Fetches the GTFS feed unless it is the same one `current` was built from.
+/// Returns `None` if nothing has changed.
+pub async fn static_feed(
+    location: &FeedLocation,
+    current: &FeedSource,
+) -> Result<Option<StaticFeed>> {
+    let (path, tmp_feed_archive, mut source) = match location {
+        FeedLocation::Url(url) => match download_feed(url, current).await? {
+            Some((tmp_feed_archive, source)) => (
+                tmp_feed_archive.path().to_path_buf(),
+                Some(tmp_feed_archive),
+                source,
+            ),
+            None => return Ok(None),
+        },
+        FeedLocation::File(path) => {
+            let modified = std::fs::metadata(path)?.modified()?;
+            let source = FeedSource {
+                last_modified: Some(DateTime::<Utc>::from(modified).to_rfc2822()),
+                ..Default::default()
+            };
+            if source.last_modified == current.last_modified {
+                log::warn!("Static feed file is not modified");
+                return Ok(None);
+            }
+            (path.clone(), None, source)
+        }
+    };
+
+    // Parsing takes a while, don't stall the bot meanwhile
+    let current = current.clone();
+    let feed = tokio::task::spawn_blocking(move || -> Result<Option<StaticFeed>> {
+        let zipfile = std::fs::File::open(path)?;
+        let mut archive = zip::ZipArchive::new(zipfile)?;
+
+        source.version = read_from_archive::<FeedInfoRecord, _>(&mut archive, &mut vec![])?
+            .into_iter()
+            .find_map(|info| info.feed_version);
+        if source.version.is_some() && source.version == current.version {
+            log::warn!("Static feed version {:?} is already loaded", source.version);
+            return Ok(None);
+        }
 
+        let mut feed = parse_feed(archive)?;
+        feed.source = source;
+        if let Some(tmp_feed_archive) = tmp_feed_archive {
+            tmp_feed_archive.close()?;
+        }
+        Ok(Some(feed))
+    })
+    .await??;
+
+    Ok(feed)
+}
+
+/// Parses a GTFS zip as it is, no matter what's loaded
+pub fn read_feed_file(path: &Path) -> Result<StaticFeed> {
+    let zipfile = std::fs::File::open(path)?;
+    parse_feed(zip::ZipArchive::new(zipfile)?)
+}
+
+/// Downloads the feed into a temporary file, unless the server says it is not modified
+async fn download_feed(
+    url: &str,
+    current: &FeedSource,
+) -> Result<Option<(NamedTempFile, FeedSource)>> {
     // Temporary storages
     let mut tmp_feed_archive = Builder::new().prefix("feed").suffix(".zip").tempfile()?;
 
     // Get fresh GTFS feed
-    let content =
-        reqwest::get("https://transport.orgp.spb.ru/Portal/transport/internalapi/gtfs/feed.zip")
-            .await?
-            .bytes()
-            .await?;
+    let mut request = FEED_CLIENT.get(url);
+    if let Some(etag) = &current.etag {
+        request = request.header(header::IF_NONE_MATCH, etag);
+    }
+    if let Some(last_modified) = &current.last_modified {
+        request = request.header(header::IF_MODIFIED_SINCE, last_modified);
+    }
+    let response = request.send().await?.error_for_status()?;
+    if response.status() == StatusCode::NOT_MODIFIED {
+        log::warn!("Static feed is not modified");
+        return Ok(None);
+    }
+
+    let header_value = |name| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|value: &header::HeaderValue| value.to_str().ok())
+            .map(|value| value.to_string())
+    };
+    let source = FeedSource {
+        etag: header_value(header::ETAG),
+        last_modified: header_value(header::LAST_MODIFIED),
+        version: None,
+    };
+
+    let content = response.bytes().await?;
 
     tmp_feed_archive.write_all(&content)?;
 
+    Ok(Some((tmp_feed_archive, source)))
+}
+
+/// Builds the feed from a GTFS archive. Broken rows are logged and skipped.
+fn parse_feed<R: Read + Seek>(mut archive: zip::ZipArchive<R>) -> Result<StaticFeed> {
+    let mut feed = StaticFeed::default();
+    let mut warnings = vec![];
+
     // Extract required data.
-    let zipfile = std::fs::File::open(tmp_feed_archive.path())?;
-    let mut archive = zip::ZipArchive::new(zipfile)?;
-    for i in 0..archive.len() {
-        let mut file = archive.by_index(i)?;
-        if let Some(path) = file.enclosed_name() {
-            if let Some(name) = path.file_name() {
-                match name.to_str().unwrap() {
-                    "routes.txt" => {
-                        let mut routes = String::new();
-                        file.read_to_string(&mut routes)?;
-                        //fill routes part of the feed
-                        routes.lines().skip(1).for_each(|line| {
-                            let left = line.splitn(4, ',').collect::<Vec<&str>>();
-                            let right = left[3].rsplitn(6, ',').collect::<Vec<&str>>();
-
-                            let id = RouteId::from(left[0]);
-                            let name = RouteName::from(right[5]);
-                            let number = RouteNumber::from(left[2]);
-                            let vehicle = Vehicle::from_str(right[3]);
-
-                            feed.routes.all.insert(id.clone(), name.clone());
-
-                            match vehicle {
-                                Ok(v) => match v {
-                                    Vehicle::Bus => {
-                                        if let Some(entry) =
-                                            feed.routes.bus.insert(number, RouteInfo { id, name })
-                                        {
-                                            log::warn!("{entry:#?} already present");
-                                        }
-                                    }
-                                    Vehicle::Trolley => {
-                                        if let Some(entry) = feed
-                                            .routes
-                                            .trolley
-                                            .insert(number, RouteInfo { id, name })
-                                        {
-                                            log::warn!("{entry:#?} already present");
-                                        }
-                                    }
-                                    Vehicle::Tram => {
-                                        if let Some(entry) =
-                                            feed.routes.tram.insert(number, RouteInfo { id, name })
-                                        {
-                                            log::warn!("{entry:#?} already present");
-                                        }
-                                    }
-                                },
-                                Err(_) => {
-                                    log::error!(
-                                        "Failed to parse vehicle type {}, entry skipped",
-                                        right[3]
-                                    );
-                                }
-                            };
-                        });
-                    }
-                    "stops.txt" => {
-                        let mut stops = String::new();
-                        file.read_to_string(&mut stops)?;
-                        //fill stops part of the feed
-                        stops.lines().skip(1).for_each(|line| {
-                            let left = line.splitn(3, ',').collect::<Vec<&str>>();
-                            let right = left[2].rsplitn(6, ',').collect::<Vec<&str>>();
-
-                            let id = StopId::from(left[0]);
-                            let name = StopName::from(right[5]);
-
-                            if let Some(entry) = feed.stops.insert(id, name) {
-                                log::warn!("{entry:#?} already present");
-                            }
-                        });
-                    }
-                    "trips.txt" => {
-                        let mut trips = String::new();
-                        file.read_to_string(&mut trips)?;
-                        //fill trips part of the feed
-                        trips.lines().skip(1).for_each(|line| {
-                            let l: Vec<&str> = line.split(',').collect();
-                            let route_id = RouteId::from(l[0]);
-                            let trip_id = TripId::from(l[2]);
-                            let direction = l[3].parse::<u8>().unwrap();
-
-                            if direction == 0 {
-                                feed.trips
-                                    .entry(route_id)
-                                    .or_insert_with(|| Trips::default())
-                                    .forward_trip
-                                    .push(trip_id);
-                            } else {
-                                feed.trips
-                                    .entry(route_id)
-                                    .or_insert_with(|| Trips::default())
-                                    .backward_trip
-                                    .push(trip_id);
-                            }
-                        });
-                    }
-                    "stop_times.txt" => {
-                        let mut stop_times = String::new();
-                        file.read_to_string(&mut stop_times)?;
-                        //fill stop times part of the feed
-                        let date = Local::now().date_naive();
-                        stop_times.lines().skip(1).for_each(|line| {
-                            let l: Vec<&str> = line.split(',').collect();
-                            let trip_id = TripId::from(l[0]);
-                            let stop_id = StopId::from(l[3]);
-                            let stop_sequence = l[4].parse::<u8>().unwrap();
-
-                            let mut hms = l[1]
-                                .split(':')
-                                .filter_map(|val| val.parse::<u32>().ok())
-                                .collect::<Vec<u32>>();
-
-                            let nextday = if hms[0] >= 24 {
-                                hms[0] -= 24;
-                                true
-                            } else {
-                                false
-                            };
-
-                            let mut timestamp = date
-                                .and_hms_opt(hms[0], hms[1], hms[2])
-                                .unwrap()
-                                .timestamp();
-                            if nextday {
-                                timestamp += 86400; // 60*60*24
-                            }
-
-                            feed.stop_times
-                                .entry(trip_id)
-                                .or_insert_with(|| Vec::new())
-                                .push(TripStop {
-                                    timestamp,
-                                    stop_id,
-                                    stop_sequence,
-                                });
-                        });
-                    }
-                    _ => (),
-                };
+    let agencies = read_from_archive::<AgencyRecord, _>(&mut archive, &mut warnings)?;
+    let routes = read_from_archive::<RouteRecord, _>(&mut archive, &mut warnings)?;
+    let stops = read_from_archive::<StopRecord, _>(&mut archive, &mut warnings)?;
+    let trips = read_from_archive::<TripRecord, _>(&mut archive, &mut warnings)?;
+    let stop_times = read_from_archive::<StopTimeRecord, _>(&mut archive, &mut warnings)?;
+    let calendar = read_from_archive::<CalendarRecord, _>(&mut archive, &mut warnings)?;
+    let calendar_dates = read_from_archive::<CalendarDateRecord, _>(&mut archive, &mut warnings)?;
+
+    // All agencies of a feed must share the same timezone
+    feed.timezone = agencies
+        .iter()
+        .find_map(|agency| match agency.agency_timezone.parse::<Tz>() {
+            Ok(tz) => Some(tz),
+            Err(e) => {
+                log::error!("Failed to parse agency timezone: {e}");
+                None
             }
+        });
+    if feed.timezone.is_none() {
+        log::error!("Feed has no timezone, falling back to UTC");
+    }
+
+    //fill routes part of the feed
+    for route in routes {
+        let id = RouteId::from(route.route_id);
+        let name = RouteName::from(route.route_long_name);
+        let number = RouteNumber::from(route.route_short_name);
+
+        // SPb own transport type is more precise than the standard one when present
+        let vehicle = route
+            .transport_type
+            .as_deref()
+            .and_then(|t| Vehicle::from_str(t).ok())
+            .or_else(|| route.route_type.and_then(Vehicle::from_route_type));
+        let Some(vehicle) = vehicle else {
+            log::error!(
+                "Failed to parse vehicle type {:?}/{:?}, entry skipped",
+                route.transport_type,
+                route.route_type
+            );
+            continue;
         };
+        feed.routes.all.insert(id.clone(), name.clone());
+        feed.routes
+            .numbers
+            .insert(id.clone(), (vehicle, number.clone()));
+        let map = feed.routes.by_vehicle.entry(vehicle).or_default();
+        if let Some(entry) = map.insert(number, RouteInfo { id, name }) {
+            log::warn!("{entry:#?} already present");
+        }
     }
 
-    tmp_feed_archive.close()?;
+    //fill stops part of the feed
+    for stop in stops {
+        let coordinates = match (stop.stop_lat, stop.stop_lon) {
+            (Some(lat), Some(lon)) => Some(Coordinates { lat, lon }),
+            _ => None,
+        };
+        if let Some(coordinates) = coordinates {
+            feed.stop_index.insert(stop.stop_id.clone(), coordinates);
+        }
+        feed.stop_names
+            .entry(stop_search::normalize(&stop.stop_name))
+            .or_default()
+            .push(stop.stop_id.clone());
+        let info = StopInfo {
+            name: stop.stop_name,
+            coordinates,
+        };
+        if let Some(entry) = feed.stops.insert(stop.stop_id, info) {
+            log::warn!("{entry:#?} already present");
+        }
+    }
 
-    Ok(feed)
-}
+    feed.stop_names.values_mut().for_each(|stops| stops.sort());
 
-pub async fn route_name(route_id: &RouteId) -> Result<RouteName> {
-    let routes = &STATIC_FEED.read().await.routes.all;
-    match routes.get(route_id) {
-        Some(name) => {
-            let mut name = name.to_uppercase();
-            name = name.replace('\"', "");
-
-            let res = name
-                .split('-')
-                .map(|substr| substr.to_case(Case::Title))
-                .collect::<Vec<String>>()
-                .join("-");
-            Ok(res)
+    //fill trips part of the feed
+    // Trips of the routes skipped above are skipped too, so they show up nowhere
+    let mut skipped_trips = 0;
+    for trip in trips {
+        if !feed.routes.all.contains_key(&trip.route_id) {
+            skipped_trips += 1;
+            continue;
+        }
+        feed.trip_services
+            .insert(trip.trip_id.clone(), trip.service_id);
+        let trips = feed.trips.entry(trip.route_id).or_default();
+        if trip.direction_id.unwrap_or(0) == 0 {
+            trips.forward_trip.push(trip.trip_id);
+        } else {
+            trips.backward_trip.push(trip.trip_id);
         }
-        None => Err(anyhow!("Can't find route by ID")),
     }
-}
+    if skipped_trips > 0 {
+        log::warn!("{skipped_trips} trips of skipped routes left out");
+    }
 
-pub async fn stop_name(stop_id: &StopId) -> Result<StopName> {
-    let stops = &STATIC_FEED.read().await.stops;
-    match stops.get(stop_id) {
-        Some(name) => {
-            let mut name = name.to_uppercase();
-            name = name.replace('\"', "");
-            Ok(name.to_case(Case::Title))
+    //fill services part of the feed
+    for service in calendar {
+        let entry = feed.services.entry(service.service_id).or_default();
+        entry.weekdays = [
+            service.monday == 1,
+            service.tuesday == 1,
+            service.wednesday == 1,
+            service.thursday == 1,
+            service.friday == 1,
+            service.saturday == 1,
+            service.sunday == 1,
+        ];
+        entry.start_date = Some(service.start_date);
+        entry.end_date = Some(service.end_date);
+    }
+    for exception in calendar_dates {
+        let entry = feed.services.entry(exception.service_id).or_default();
+        match exception.exception_type {
+            1 => entry.added.insert(exception.date),
+            2 => entry.removed.insert(exception.date),
+            _ => {
+                log::warn!(
+                    "Unknown exception type {} in calendar_dates.txt",
+                    exception.exception_type
+                );
+                false
+            }
+        };
+    }
+
+    //fill stop times part of the feed
+    for stop_time in stop_times {
+        if !feed.trip_services.contains_key(&stop_time.trip_id) {
+            continue;
         }
-        None => Err(anyhow!("failed to get stop by ID")),
+        let time = stop_time
+            .arrival_time
+            .as_deref()
+            .filter(|t| !t.is_empty())
+            .or(stop_time.departure_time.as_deref())
+            .and_then(parse_time);
+        // Only timepoints have times, the rest are interpolated by GTFS consumers. We don't do that (yet).
+        let Some(time) = time else {
+            continue;
+        };
+
+        feed.stop_times
+            .entry(stop_time.trip_id)
+            .or_default()
+            .push(TripStop {
+                time,
+                stop_id: stop_time.stop_id,
+                stop_sequence: stop_time.stop_sequence,
+            });
     }
-}
+    // Rows of stop_times.txt are not guaranteed to be ordered
+    feed.stop_times
+        .values_mut()
+        .for_each(|stops| stops.sort_by_key(|stop| stop.stop_sequence));
 
-pub async fn stops_on_route(route_id: &RouteId, direction: &str) -> Result<Vec<StopId>> {
-    let feed = STATIC_FEED.read().await;
+    index::build_indexes(&mut feed);
 
-    let trips = if direction == "0" {
-        &feed.trips.get(route_id).unwrap().forward_trip
-    } else {
-        &feed.trips.get(route_id).unwrap().backward_trip
-    };
-    let mut res = vec![];
+    for warning in &warnings {
+        log::warn!("{warning}");
+    }
+    if !warnings.is_empty() {
+        log::error!("{} rows of the feed were skipped", warnings.len());
+    }
+
+    Ok(feed)
+}
 
-    let stops = trips.iter().find_map(|trip| feed.stop_times.get(trip));
-    match stops {
-        Some(stops) => {
-            stops.iter().for_each(|stop| res.push(stop.stop_id.clone()));
-            Ok(res)
+fn read_from_archive<T: GtfsRecord, R: Read + Seek>(
+    archive: &mut zip::ZipArchive<R>,
+    warnings: &mut Vec<ParseWarning>,
+) -> Result<Vec<T>> {
+    let name = archive
+        .file_names()
+        .find(|name| Path::new(name).file_name() == Some(OsStr::new(T::FILE)))
+        .map(|name| name.to_string());
+    match name {
+        Some(name) => Ok(read_records(archive.by_name(&name)?, warnings)),
+        None if T::OPTIONAL => Ok(vec![]),
+        None => {
+            warnings.push(ParseWarning {
+                file: T::FILE,
+                line: 0,
+                message: "file is missing from the archive".to_string(),
+            });
+            Ok(vec![])
         }
-        None => Err(anyhow!("Couldn't find stops for this route and direction")),
     }
 }
 
-pub async fn arrival_forecast(route_id: &RouteId, stop_id: &StopId) -> Result<Vec<i64>> {
-    let url = "https://transport.orgp.spb.ru/Portal/transport/internalapi/gtfs/realtime/stopforecast?stopID=".to_string() + stop_id.as_str();
-    let resp = reqwest::get(url).await?.bytes().await?;
-    let message = FeedMessage::decode(resp)?;
-
-    let timestamp = SystemTime::now()
+/// Seconds since the message was generated, `None` if the feed doesn't say
+pub fn feed_age(message: &FeedMessage) -> Option<u64> {
+    let now = SystemTime::now()
         .duration_since(SystemTime::UNIX_EPOCH)
         .unwrap()
-        .as_secs() as i64;
-
-    let mut waiting_time = vec![];
-
-    for entity in message.entity {
-        if &entity.id == route_id {
-            if let Some(update) = entity.trip_update {
-                for stop_time in update.stop_time_update {
-                    if let Some(arrival) = stop_time.arrival {
-                        if let Some(time) = arrival.time {
-                            let time_left = time - timestamp;
-                            if time_left > 0 {
-                                waiting_time.push(time_left);
-                            }
-                        }
-                    }
-                }
-            }
-        }
-    }
-
-    Ok(waiting_time)
+        .as_secs();
+    message
+        .header
+        .timestamp
+        .map(|timestamp| now.saturating_sub(timestamp))
 }
 
-pub async fn arrival_timetable(
-    route_id: &RouteId,
-    direction: &str,
-    stop_id: &StopId,
-) -> Result<Vec<i64>> {
-    let timestamp = Local::now().timestamp();
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let mut timetable = vec![];
+    fn date(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2026, 3, day).unwrap()
+    }
 
-    let feed = STATIC_FEED.read().await;
+    /// Runs on weekdays from the 2nd (Monday) to the 22nd of March 2026
+    fn weekdays() -> Service {
+        Service {
+            weekdays: [true, true, true, true, true, false, false],
+            start_date: Some(date(2)),
+            end_date: Some(date(22)),
+            ..Default::default()
+        }
+    }
 
-    let trips = feed
-        .trips
-        .get(route_id)
-        .ok_or(anyhow!("Failed to find trips for this route ID"))?;
+    #[test]
+    fn service_runs_on_its_weekdays_within_dates() {
+        let service = weekdays();
+        assert!(service.is_active(date(2)));
+        assert!(service.is_active(date(6)));
+        assert!(!service.is_active(date(7)));
+        assert!(!service.is_active(date(8)));
+        assert!(service.is_active(date(20)));
+        assert!(!service.is_active(date(1)));
+        assert!(!service.is_active(date(23)));
+    }
 
-    let trip_ids = if direction == "0" {
-        &trips.forward_trip
-    } else {
-        &trips.backward_trip
-    };
+    #[test]
+    fn service_exceptions_win() {
+        let mut service = weekdays();
+        service.added.insert(date(7));
+        service.removed.insert(date(9));
+        assert!(service.is_active(date(7)));
+        assert!(!service.is_active(date(9)));
+    }
 
-    for trip in trip_ids {
-        let trip_info = feed
-            .stop_times
-            .get(trip)
-            .ok_or(anyhow!("Failed to fetch trip info"))?;
-        for trip_stop in trip_info {
-            if &trip_stop.stop_id == stop_id && trip_stop.timestamp > timestamp {
-                timetable.push(trip_stop.timestamp);
-            }
-        }
+    #[test]
+    fn service_of_calendar_dates_only() {
+        let service = Service {
+            added: HashSet::from([date(8)]),
+            ..Default::default()
+        };
+        assert!(service.is_active(date(8)));
+        assert!(!service.is_active(date(9)));
     }
 
-    timetable.sort();
+    #[test]
+    fn trips_run_every_day_without_calendars() {
+        let mut feed = StaticFeed::default();
+        let trip = TripId::from("t");
+        assert!(feed.trip_is_active(&trip, date(8)));
+
+        feed.services
+            .insert(ServiceId::from("weekdays"), weekdays());
+        assert!(!feed.trip_is_active(&trip, date(9)));
+        feed.trip_services
+            .insert(trip.clone(), ServiceId::from("weekdays"));
+        assert!(feed.trip_is_active(&trip, date(9)));
+        assert!(!feed.trip_is_active(&trip, date(8)));
+    }
 
-    Ok(timetable)
+    #[test]
+    fn routes_of_unknown_vehicle_types_left_out() {
+        let feed = test_feed::parse(&[
+            (
+                "agency.txt",
+                "agency_name,agency_url,agency_timezone\nTest,http://example.com,Europe/Berlin\n",
+            ),
+            (
+                "routes.txt",
+                "route_id,route_short_name,route_type\nR1,1,0\nZ,Z,42\n",
+            ),
+            ("stops.txt", "stop_id,stop_name\nA,Alpha\nB,Beta\n"),
+            (
+                "trips.txt",
+                "route_id,service_id,trip_id\nR1,daily,r\nZ,daily,z\n",
+            ),
+            (
+                "stop_times.txt",
+                "trip_id,arrival_time,departure_time,stop_id,stop_sequence\n\
+                 r,08:00:00,08:00:00,A,1\n\
+                 z,09:00:00,09:00:00,A,1\n\
+                 z,09:05:00,09:05:00,B,2\n",
+            ),
+        ]);
+        assert_eq!(feed.routes.all.keys().collect::<Vec<_>>(), vec!["R1"]);
+        assert!(!feed.trips.contains_key("Z"));
+        assert!(!feed.stop_times.contains_key("z"));
+        assert_eq!(feed.stop_routes["A"].len(), 1);
+        assert!(!feed.stop_routes.contains_key("B"));
+    }
 }
@@ -0,0 +1,153 @@
+use std::collections::BTreeSet;
+
+use gtfs_rt::{FeedMessage, TranslatedString};
+
+use super::{RouteId, StaticFeed, StopId};
+
+/// Language of the texts we show, if the feed has several
+const LANGUAGE: &str = "ru";
+
+/// What an alert is about: a route, a stop, or a route at a stop when both are set
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, serde::Serialize, serde::Deserialize)]
+pub struct InformedEntity {
+    pub route_id: Option<RouteId>,
+    pub stop_id: Option<StopId>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ServiceAlert {
+    pub id: String,
+    pub header: String,
+    pub description: String,
+    pub informed: BTreeSet<InformedEntity>,
+    /// Unix timestamps, open ended when missing
+    pub active_periods: Vec<(Option<u64>, Option<u64>)>,
+}
+
+impl ServiceAlert {
+    /// Alerts without periods are active until removed from the feed
+    pub fn is_active(&self, now: u64) -> bool {
+        self.active_periods.is_empty()
+            || self.active_periods.iter().any(|(start, end)| {
+                start.is_none_or(|start| start <= now) && end.is_none_or(|end| now < end)
+            })
+    }
+
+    /// Without `stop_id` the alerts of the route at any of its stops count
+    pub fn affects(&self, route_id: &RouteId, stop_id: Option<&StopId>) -> bool {
+        self.informed
+            .iter()
+            .any(|entity| match (&entity.route_id, &entity.stop_id) {
+                (Some(route), Some(stop)) => {
+                    route == route_id && stop_id.is_none_or(|id| id == stop)
+                }
+                (Some(route), None) => route == route_id,
+                (None, Some(stop)) => stop_id == Some(stop),
+                (None, None) => false,
+            })
+    }
+}
+
+fn translation(text: &Option<TranslatedString>) -> String {
+    let Some(text) = text else {
+        return String::new();
+    };
+    text.translation
+        .iter()
+        .find(|t| t.language.as_deref() == Some(LANGUAGE))
+        .or(text.translation.first())
+        .map(|t| t.text.clone())
+        .unwrap_or_default()
+}
+
+impl StaticFeed {
+    /// Alert entities of the message with the routes and stops they inform about
+    pub fn service_alerts(&self, message: &FeedMessage) -> Vec<ServiceAlert> {
+        let mut alerts = vec![];
+
+        for entity in &message.entity {
+            let Some(alert) = &entity.alert else {
+                continue;
+            };
+            if entity.is_deleted == Some(true) {
+                continue;
+            }
+
+            let mut entities = BTreeSet::new();
+            for informed in &alert.informed_entity {
+                let trip_route = informed.trip.as_ref().and_then(|trip| {
+                    trip.route_id.clone().or_else(|| {
+                        trip.trip_id
+                            .as_ref()
+                            .and_then(|trip_id| self.trip_routes.get(trip_id))
+                            .map(|route| route.route_id.clone())
+                    })
+                });
+                let entity = InformedEntity {
+                    route_id: informed.route_id.clone().or(trip_route),
+                    stop_id: informed.stop_id.clone(),
+                };
+                if entity.route_id.is_some() || entity.stop_id.is_some() {
+                    entities.insert(entity);
+                }
+            }
+
+            alerts.push(ServiceAlert {
+                id: entity.id.clone(),
+                header: translation(&alert.header_text),
+                description: translation(&alert.description_text),
+                informed: entities,
+                active_periods: alert
+                    .active_period
+                    .iter()
+                    .map(|period| (period.start, period.end))
+                    .collect(),
+            });
+        }
+
+        alerts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn alert(informed: &[(Option<&str>, Option<&str>)]) -> ServiceAlert {
+        ServiceAlert {
+            id: String::from("1"),
+            header: String::new(),
+            description: String::new(),
+            informed: informed
+                .iter()
+                .map(|(route_id, stop_id)| InformedEntity {
+                    route_id: route_id.map(String::from),
+                    stop_id: stop_id.map(String::from),
+                })
+                .collect(),
+            active_periods: vec![],
+        }
+    }
+
+    #[test]
+    fn route_at_stop_needs_both() {
+        let alert = alert(&[(Some("X"), Some("S"))]);
+        let (x, y) = (String::from("X"), String::from("Y"));
+        let (s, t) = (String::from("S"), String::from("T"));
+        assert!(alert.affects(&x, Some(&s)));
+        assert!(!alert.affects(&x, Some(&t)));
+        assert!(!alert.affects(&y, Some(&s)));
+        assert!(alert.affects(&x, None));
+    }
+
+    #[test]
+    fn route_or_stop_alone() {
+        let alert = alert(&[(Some("X"), None), (None, Some("S"))]);
+        let (x, y) = (String::from("X"), String::from("Y"));
+        let (s, t) = (String::from("S"), String::from("T"));
+        assert!(alert.affects(&x, Some(&t)));
+        assert!(alert.affects(&y, Some(&s)));
+        assert!(!alert.affects(&y, Some(&t)));
+        assert!(!alert.affects(&y, None));
+    }
+}
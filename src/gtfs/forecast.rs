@@ -0,0 +1,499 @@
+use chrono::NaiveDate;
+use gtfs_rt::trip_descriptor::ScheduleRelationship as TripRelationship;
+use gtfs_rt::trip_update::stop_time_update::ScheduleRelationship as StopRelationship;
+use gtfs_rt::FeedMessage;
+
+use super::{RouteId, ScheduledArrival, StaticFeed, StopId, TripId};
+
+/// Predicted arrival of a vehicle at the stop
+#[derive(Debug, Clone, PartialEq)]
+pub struct Forecast {
+    pub trip_id: Option<TripId>,
+    pub vehicle_id: Option<String>,
+    /// Unix timestamp
+    pub arrival: i64,
+    /// Seconds behind the timetable, negative when ahead
+    pub delay: Option<i32>,
+    /// Unix timestamp of the prediction itself, the feed's if the update has none
+    pub timestamp: Option<u64>,
+}
+
+impl Forecast {
+    /// Still to come and predicted no more than `max_age` seconds ago. A frozen feed keeps
+    /// predicting arrivals that are long gone or never come.
+    pub fn is_current(&self, now: u64, max_age: u64) -> bool {
+        self.arrival > now as i64
+            && self
+                .timestamp
+                .is_none_or(|made| now.saturating_sub(made) <= max_age)
+    }
+}
+
+/// A timetable trip the realtime feed says won't call at the stop
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cancellation {
+    pub trip_id: TripId,
+    /// Service day of the trip, any day if the feed doesn't say
+    pub start_date: Option<NaiveDate>,
+    /// The trip runs but passes the stop by
+    pub skipped: bool,
+}
+
+impl Cancellation {
+    pub fn applies_to(&self, arrival: &ScheduledArrival) -> bool {
+        self.trip_id == arrival.trip_id
+            && self
+                .start_date
+                .is_none_or(|date| date == arrival.service_date)
+    }
+}
+
+impl StaticFeed {
+    /// Whether a realtime trip goes along the route in the given direction.
+    /// `fallback_route` is used when the descriptor has no route.
+    pub(super) fn trip_matches(
+        &self,
+        trip: &gtfs_rt::TripDescriptor,
+        fallback_route: Option<&str>,
+        route_id: &RouteId,
+        direction: &str,
+        stop_id: &StopId,
+    ) -> bool {
+        // Static feed knows the trip best. ADDED trips aren't there and go by the route.
+        if let Some(route) = trip
+            .trip_id
+            .as_ref()
+            .and_then(|trip_id| self.trip_routes.get(trip_id))
+        {
+            return &route.route_id == route_id && route.direction == direction;
+        }
+
+        if trip.route_id.as_deref().or(fallback_route) != Some(route_id.as_str()) {
+            return false;
+        }
+        if let Some(direction_id) = trip.direction_id {
+            return direction_id.to_string() == direction;
+        }
+
+        // No way to tell the direction. Trust it only if the route passes the stop one way,
+        // otherwise vehicles going back from a terminal would count too.
+        self.stop_routes.get(stop_id).is_some_and(|routes| {
+            routes
+                .iter()
+                .filter(|route| &route.route_id == route_id)
+                .all(|route| route.direction == direction)
+        })
+    }
+
+    /// Forecasts of `message` for the route going in `direction` and calling at `stop_id`
+    pub fn match_forecasts(
+        &self,
+        message: &FeedMessage,
+        route_id: &RouteId,
+        direction: &str,
+        stop_id: &StopId,
+    ) -> Vec<Forecast> {
+        let mut forecasts = vec![];
+
+        for entity in &message.entity {
+            let Some(update) = &entity.trip_update else {
+                continue;
+            };
+            // SPb puts route ID into the entity ID and leaves the descriptor empty
+            let fallback_route = Some(entity.id.as_str());
+            if !self.trip_matches(&update.trip, fallback_route, route_id, direction, stop_id) {
+                continue;
+            }
+            if update.trip.schedule_relationship() == TripRelationship::Canceled {
+                continue;
+            }
+
+            for stop_time in &update.stop_time_update {
+                if stop_time.stop_id.as_ref().is_some_and(|id| id != stop_id) {
+                    continue;
+                }
+                // Skipped stops aren't arrivals, and without data the timetable knows better
+                if stop_time.schedule_relationship() != StopRelationship::Scheduled {
+                    continue;
+                }
+                let event = stop_time.arrival.as_ref().or(stop_time.departure.as_ref());
+                if let Some(time) = event.and_then(|event| event.time) {
+                    forecasts.push(Forecast {
+                        trip_id: update.trip.trip_id.clone(),
+                        vehicle_id: update.vehicle.as_ref().and_then(|v| v.id.clone()),
+                        arrival: time,
+                        delay: event.and_then(|event| event.delay).or(update.delay),
+                        timestamp: update.timestamp.or(message.header.timestamp),
+                    });
+                }
+            }
+        }
+
+        forecasts.sort_by_key(|forecast| forecast.arrival);
+        forecasts
+    }
+
+    /// Timetable trips of the route that `message` cancels or sends past `stop_id`
+    pub fn match_cancellations(
+        &self,
+        message: &FeedMessage,
+        route_id: &RouteId,
+        direction: &str,
+        stop_id: &StopId,
+    ) -> Vec<Cancellation> {
+        let mut cancellations = vec![];
+
+        for entity in &message.entity {
+            let Some(update) = &entity.trip_update else {
+                continue;
+            };
+            // Without a trip ID there is no telling which departure is gone
+            let Some(trip_id) = &update.trip.trip_id else {
+                continue;
+            };
+            let fallback_route = Some(entity.id.as_str());
+            if !self.trip_matches(&update.trip, fallback_route, route_id, direction, stop_id) {
+                continue;
+            }
+
+            let skipped = update.stop_time_update.iter().any(|stop_time| {
+                stop_time.stop_id.as_ref().is_none_or(|id| id == stop_id)
+                    && stop_time.schedule_relationship() == StopRelationship::Skipped
+            });
+            let canceled = update.trip.schedule_relationship() == TripRelationship::Canceled;
+            if canceled || skipped {
+                cancellations.push(Cancellation {
+                    trip_id: trip_id.clone(),
+                    start_date: update
+                        .trip
+                        .start_date
+                        .as_ref()
+                        .and_then(|date| NaiveDate::parse_from_str(date, "%Y%m%d").ok()),
+                    skipped: !canceled,
+                });
+            }
+        }
+
+        cancellations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use gtfs_rt::trip_update::{StopTimeEvent, StopTimeUpdate};
+    use gtfs_rt::{FeedEntity, TripDescriptor, TripUpdate, VehicleDescriptor};
+
+    use super::*;
+    use crate::gtfs::test_feed;
+
+    fn trip(
+        trip_id: Option<&str>,
+        route_id: Option<&str>,
+        direction_id: Option<u32>,
+    ) -> TripDescriptor {
+        TripDescriptor {
+            trip_id: trip_id.map(String::from),
+            route_id: route_id.map(String::from),
+            direction_id,
+            ..Default::default()
+        }
+    }
+
+    fn stop_time(stop_id: &str, time: i64) -> StopTimeUpdate {
+        StopTimeUpdate {
+            stop_id: Some(stop_id.to_string()),
+            arrival: Some(StopTimeEvent {
+                time: Some(time),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn stop_time_with(stop_id: &str, time: i64, relationship: StopRelationship) -> StopTimeUpdate {
+        let mut update = stop_time(stop_id, time);
+        update.set_schedule_relationship(relationship);
+        update
+    }
+
+    fn entity(id: &str, trip: TripDescriptor, stop_times: Vec<StopTimeUpdate>) -> FeedEntity {
+        FeedEntity {
+            id: id.to_string(),
+            trip_update: Some(TripUpdate {
+                trip,
+                vehicle: Some(VehicleDescriptor {
+                    id: Some(format!("v-{id}")),
+                    ..Default::default()
+                }),
+                stop_time_update: stop_times,
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn message(entities: Vec<FeedEntity>) -> FeedMessage {
+        FeedMessage {
+            entity: entities,
+            ..Default::default()
+        }
+    }
+
+    fn matched(
+        feed: &StaticFeed,
+        message: &FeedMessage,
+        route_id: &str,
+        direction: &str,
+        stop_id: &str,
+    ) -> Vec<(String, i64)> {
+        feed.match_forecasts(
+            message,
+            &route_id.to_string(),
+            direction,
+            &stop_id.to_string(),
+        )
+        .into_iter()
+        .map(|f| (f.vehicle_id.unwrap_or_default(), f.arrival))
+        .collect()
+    }
+
+    fn forecasts(message: &FeedMessage, direction: &str, stop_id: &str) -> Vec<(String, i64)> {
+        matched(&test_feed::sample(), message, "R1", direction, stop_id)
+    }
+
+    fn cancellations(message: &FeedMessage, stop_id: &str) -> Vec<Cancellation> {
+        test_feed::sample().match_cancellations(
+            message,
+            &String::from("R1"),
+            "0",
+            &stop_id.to_string(),
+        )
+    }
+
+    #[test]
+    fn known_trips_go_by_the_static_direction() {
+        let message = message(vec![
+            entity("1", trip(Some("f1"), None, None), vec![stop_time("B", 200)]),
+            entity("2", trip(Some("b1"), None, None), vec![stop_time("B", 100)]),
+        ]);
+        assert_eq!(
+            forecasts(&message, "0", "B"),
+            vec![(String::from("v-1"), 200)]
+        );
+        assert_eq!(
+            forecasts(&message, "1", "B"),
+            vec![(String::from("v-2"), 100)]
+        );
+        assert!(forecasts(&message, "0", "C").is_empty());
+    }
+
+    #[test]
+    fn route_taken_from_the_entity_id_when_the_descriptor_has_none() {
+        let message = message(vec![
+            // SPb style: the route in the entity ID and nothing else in the descriptor
+            entity("R1", trip(None, None, Some(0)), vec![stop_time("B", 100)]),
+            entity("R2", trip(None, None, Some(0)), vec![stop_time("B", 200)]),
+            // The route of the descriptor wins over the entity ID
+            entity(
+                "7",
+                trip(None, Some("R1"), Some(0)),
+                vec![stop_time("B", 300)],
+            ),
+            entity(
+                "R1",
+                trip(None, Some("R2"), Some(0)),
+                vec![stop_time("B", 400)],
+            ),
+        ]);
+        assert_eq!(
+            forecasts(&message, "0", "B"),
+            vec![(String::from("v-R1"), 100), (String::from("v-7"), 300)]
+        );
+    }
+
+    #[test]
+    fn unknown_direction_at_a_stop_served_both_ways() {
+        let message = message(vec![
+            entity("R1", trip(None, None, None), vec![stop_time("B", 100)]),
+            entity("R1", trip(None, None, Some(1)), vec![stop_time("B", 200)]),
+        ]);
+        // R1 passes B both ways, so the first one can't be told apart
+        assert!(forecasts(&message, "0", "B").is_empty());
+        assert_eq!(
+            forecasts(&message, "1", "B"),
+            vec![(String::from("v-R1"), 200)]
+        );
+    }
+
+    #[test]
+    fn unknown_direction_at_a_stop_served_one_way() {
+        // A loop: forward A - B - C, backward C - D - A
+        let feed = test_feed::parse(&[
+            (
+                "agency.txt",
+                "agency_name,agency_url,agency_timezone\nTest,http://example.com,Europe/Berlin\n",
+            ),
+            (
+                "routes.txt",
+                "route_id,route_short_name,route_type\nL,L,3\n",
+            ),
+            (
+                "stops.txt",
+                "stop_id,stop_name\nA,Alpha\nB,Beta\nC,Gamma\nD,Delta\n",
+            ),
+            (
+                "trips.txt",
+                "route_id,service_id,trip_id,direction_id\nL,daily,f,0\nL,daily,b,1\n",
+            ),
+            (
+                "stop_times.txt",
+                "trip_id,arrival_time,departure_time,stop_id,stop_sequence\n\
+                 f,08:00:00,08:00:00,A,1\n\
+                 f,08:05:00,08:05:00,B,2\n\
+                 f,08:10:00,08:10:00,C,3\n\
+                 b,09:00:00,09:00:00,C,1\n\
+                 b,09:05:00,09:05:00,D,2\n\
+                 b,09:10:00,09:10:00,A,3\n",
+            ),
+        ]);
+        let message = message(vec![entity(
+            "L",
+            trip(None, None, None),
+            vec![
+                stop_time("B", 100),
+                stop_time("D", 200),
+                stop_time("A", 300),
+            ],
+        )]);
+        assert_eq!(
+            matched(&feed, &message, "L", "0", "B"),
+            vec![(String::from("v-L"), 100)]
+        );
+        assert_eq!(
+            matched(&feed, &message, "L", "1", "D"),
+            vec![(String::from("v-L"), 200)]
+        );
+        // Both ways pass A
+        assert!(matched(&feed, &message, "L", "0", "A").is_empty());
+        assert!(matched(&feed, &message, "L", "1", "A").is_empty());
+    }
+
+    #[test]
+    fn prediction_time_of_the_update_or_the_feed() {
+        let mut message = message(vec![
+            entity("1", trip(Some("f1"), None, None), vec![stop_time("B", 300)]),
+            entity("2", trip(Some("f2"), None, None), vec![stop_time("B", 400)]),
+        ]);
+        message.header.timestamp = Some(100);
+        message.entity[0].trip_update.as_mut().unwrap().timestamp = Some(150);
+
+        let feed = test_feed::sample();
+        let timestamps = feed
+            .match_forecasts(&message, &String::from("R1"), "0", &String::from("B"))
+            .into_iter()
+            .map(|forecast| forecast.timestamp)
+            .collect::<Vec<_>>();
+        assert_eq!(timestamps, vec![Some(150), Some(100)]);
+    }
+
+    #[test]
+    fn stale_and_past_forecasts_are_not_current() {
+        let forecast = |arrival, timestamp| Forecast {
+            trip_id: None,
+            vehicle_id: None,
+            arrival,
+            delay: None,
+            timestamp,
+        };
+        let max_age = 180;
+        assert!(forecast(1000, Some(500)).is_current(600, max_age));
+        assert!(forecast(1000, Some(500)).is_current(680, max_age));
+        assert!(!forecast(1000, Some(500)).is_current(681, max_age));
+        assert!(!forecast(1000, Some(900)).is_current(1000, max_age));
+        // Without a timestamp there is no telling, only the arrival counts
+        assert!(forecast(1000, None).is_current(999, max_age));
+    }
+
+    #[test]
+    fn cancelled_trips_are_no_forecasts() {
+        let mut canceled = trip(Some("f1"), None, None);
+        canceled.set_schedule_relationship(TripRelationship::Canceled);
+        canceled.start_date = Some(String::from("20260310"));
+        let message = message(vec![entity("1", canceled, vec![])]);
+
+        assert!(forecasts(&message, "0", "B").is_empty());
+        assert_eq!(
+            cancellations(&message, "B"),
+            vec![Cancellation {
+                trip_id: String::from("f1"),
+                start_date: NaiveDate::from_ymd_opt(2026, 3, 10),
+                skipped: false,
+            }]
+        );
+    }
+
+    #[test]
+    fn skipped_stop_is_passed_by() {
+        let message = message(vec![entity(
+            "1",
+            trip(Some("f1"), None, None),
+            vec![
+                stop_time_with("B", 100, StopRelationship::Skipped),
+                stop_time("C", 200),
+            ],
+        )]);
+
+        assert!(forecasts(&message, "0", "B").is_empty());
+        assert_eq!(
+            forecasts(&message, "0", "C"),
+            vec![(String::from("v-1"), 200)]
+        );
+        let skipped = cancellations(&message, "B");
+        assert_eq!(skipped.len(), 1);
+        assert!(skipped[0].skipped);
+        assert_eq!(skipped[0].start_date, None);
+        assert!(cancellations(&message, "C").is_empty());
+    }
+
+    #[test]
+    fn added_trips_go_by_the_route() {
+        let mut added = trip(Some("extra"), Some("R1"), Some(0));
+        added.set_schedule_relationship(TripRelationship::Added);
+        let message = message(vec![entity("1", added, vec![stop_time("B", 100)])]);
+
+        assert_eq!(
+            forecasts(&message, "0", "B"),
+            vec![(String::from("v-1"), 100)]
+        );
+        assert!(forecasts(&message, "1", "B").is_empty());
+        assert!(cancellations(&message, "B").is_empty());
+    }
+
+    #[test]
+    fn stops_without_data_are_left_to_the_timetable() {
+        let message = message(vec![entity(
+            "1",
+            trip(Some("f1"), None, None),
+            vec![stop_time_with("B", 100, StopRelationship::NoData)],
+        )]);
+        assert!(forecasts(&message, "0", "B").is_empty());
+        assert!(cancellations(&message, "B").is_empty());
+    }
+
+    #[test]
+    fn cancellation_applies_to_its_service_day() {
+        let cancellation = Cancellation {
+            trip_id: String::from("f1"),
+            start_date: NaiveDate::from_ymd_opt(2026, 3, 10),
+            skipped: false,
+        };
+        let arrival = |trip_id: &str, day: u32| ScheduledArrival {
+            trip_id: trip_id.to_string(),
+            service_date: NaiveDate::from_ymd_opt(2026, 3, day).unwrap(),
+            time: 0,
+        };
+        assert!(cancellation.applies_to(&arrival("f1", 10)));
+        assert!(!cancellation.applies_to(&arrival("f1", 11)));
+        assert!(!cancellation.applies_to(&arrival("f2", 10)));
+    }
+}
@@ -0,0 +1,119 @@
+use std::collections::{HashMap, HashSet};
+
+use super::{RouteDirection, RouteId, StaticFeed, StopId, TripId};
+
+/// A trip calling at a stop
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct StopVisit {
+    pub trip_id: TripId,
+    pub stop_sequence: u32,
+    /// Service day offset, same as `TripStop::time`
+    pub time: u32,
+}
+
+/// Distinct stop sequences of a route, the most frequent first
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
+pub struct RoutePatterns {
+    pub forward: Vec<Vec<StopId>>,
+    pub backward: Vec<Vec<StopId>>,
+}
+
+impl RoutePatterns {
+    pub fn direction(&self, direction: &str) -> &Vec<Vec<StopId>> {
+        if direction == "0" {
+            &self.forward
+        } else {
+            &self.backward
+        }
+    }
+}
+
+/// Lookup tables derived from trips and stop times, so queries don't walk the whole feed
+pub fn build_indexes(feed: &mut StaticFeed) {
+    feed.trip_routes.clear();
+    let mut stop_routes: HashMap<StopId, HashSet<RouteDirection>> = HashMap::new();
+
+    for (route_id, trips) in &feed.trips {
+        let mut patterns = RoutePatterns::default();
+        for (direction, trip_ids) in [("0", &trips.forward_trip), ("1", &trips.backward_trip)] {
+            let route = RouteDirection {
+                route_id: route_id.clone(),
+                direction: direction.to_string(),
+            };
+
+            let mut pattern_trips: HashMap<Vec<&StopId>, usize> = HashMap::new();
+            for trip_id in trip_ids {
+                feed.trip_routes.insert(trip_id.clone(), route.clone());
+
+                let Some(trip_stops) = feed.stop_times.get(trip_id) else {
+                    continue;
+                };
+                for trip_stop in trip_stops {
+                    stop_routes
+                        .entry(trip_stop.stop_id.clone())
+                        .or_default()
+                        .insert(route.clone());
+                }
+                *pattern_trips
+                    .entry(trip_stops.iter().map(|s| &s.stop_id).collect())
+                    .or_default() += 1;
+            }
+
+            let mut pattern_trips = pattern_trips.into_iter().collect::<Vec<_>>();
+            // Ties go to the longer pattern, then to the stops themselves, so the result doesn't depend on hashing
+            pattern_trips.sort_by(|a, b| {
+                b.1.cmp(&a.1)
+                    .then_with(|| b.0.len().cmp(&a.0.len()))
+                    .then_with(|| a.0.cmp(&b.0))
+            });
+            let ordered = pattern_trips
+                .into_iter()
+                .map(|(stops, _)| stops.into_iter().cloned().collect())
+                .collect();
+            if direction == "0" {
+                patterns.forward = ordered;
+            } else {
+                patterns.backward = ordered;
+            }
+        }
+        feed.route_patterns.insert(route_id.clone(), patterns);
+    }
+
+    feed.stop_routes = stop_routes
+        .into_iter()
+        .map(|(stop_id, routes)| (stop_id, routes.into_iter().collect()))
+        .collect();
+
+    feed.stop_visits.clear();
+    for (trip_id, trip_stops) in &feed.stop_times {
+        // Trips of unknown routes can't be asked about
+        let Some(route) = feed.trip_routes.get(trip_id) else {
+            continue;
+        };
+        for trip_stop in trip_stops {
+            feed.stop_visits
+                .entry(trip_stop.stop_id.clone())
+                .or_default()
+                .entry(route.clone())
+                .or_default()
+                .push(StopVisit {
+                    trip_id: trip_id.clone(),
+                    stop_sequence: trip_stop.stop_sequence,
+                    time: trip_stop.time,
+                });
+        }
+    }
+    feed.stop_visits
+        .values_mut()
+        .flat_map(|routes| routes.values_mut())
+        .for_each(|visits| visits.sort_by_key(|visit| visit.time));
+}
+
+impl StaticFeed {
+    /// Most common sequence of stops of the route in the given direction
+    pub fn route_stops(&self, route_id: &RouteId, direction: &str) -> Option<&Vec<StopId>> {
+        self.route_patterns
+            .get(route_id)
+            .and_then(|patterns| patterns.direction(direction).first())
+    }
+}
@@ -0,0 +1,296 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use gtfs_rt::FeedMessage;
+use lazy_static::lazy_static;
+use prost::Message;
+use rand::Rng;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+const MAX_ATTEMPTS: u32 = 3;
+const BACKOFF_BASE: Duration = Duration::from_millis(250);
+/// Failed requests in a row that open the circuit
+const FAILURE_THRESHOLD: u32 = 5;
+/// How long an open circuit fails requests without trying
+const OPEN_DURATION: Duration = Duration::from_secs(30);
+
+lazy_static! {
+    /// Realtime requests share connections
+    static ref CLIENT: reqwest::Client = reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .connect_timeout(CONNECT_TIMEOUT)
+        .build()
+        .unwrap();
+    /// Circuit breakers by host, so one dead endpoint doesn't take the others down
+    static ref BREAKERS: Mutex<HashMap<String, CircuitBreaker>> = Mutex::new(HashMap::new());
+}
+
+#[derive(Default)]
+struct CircuitBreaker {
+    failures: u32,
+    open_until: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    fn allows(&mut self, host: &str, now: Instant) -> bool {
+        match self.open_until {
+            Some(until) if now < until => false,
+            Some(_) => {
+                // Half open: let one request through. The rest keep failing at once until it
+                // succeeds or another OPEN_DURATION passes.
+                log::warn!("Trying realtime endpoint {host} again");
+                self.open_until = Some(now + OPEN_DURATION);
+                true
+            }
+            None => true,
+        }
+    }
+
+    fn succeeded(&mut self, host: &str) {
+        if self.open_until.is_some() {
+            log::warn!("Realtime endpoint {host} is back");
+        }
+        self.failures = 0;
+        self.open_until = None;
+    }
+
+    fn failed(&mut self, host: &str, now: Instant) {
+        self.failures += 1;
+        if self.failures >= FAILURE_THRESHOLD {
+            if self.open_until.is_none() {
+                log::error!("Realtime endpoint {host} is down, pausing requests");
+            }
+            self.open_until = Some(now + OPEN_DURATION);
+        }
+    }
+}
+
+fn with_breaker<T>(
+    breakers: &Mutex<HashMap<String, CircuitBreaker>>,
+    host: &str,
+    f: impl FnOnce(&mut CircuitBreaker) -> T,
+) -> T {
+    f(breakers
+        .lock()
+        .unwrap()
+        .entry(host.to_string())
+        .or_default())
+}
+
+/// An empty body decodes into an empty message, which would look like nothing is going on, so
+/// a message without a header counts as a failed request
+fn decode(bytes: &[u8]) -> Result<FeedMessage> {
+    let message = FeedMessage::decode(bytes)?;
+    if message.header.gtfs_realtime_version.is_empty() {
+        return Err(anyhow!("Realtime feed has no header"));
+    }
+    Ok(message)
+}
+
+async fn fetch_once(url: &str) -> Result<FeedMessage> {
+    let resp = CLIENT
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await?;
+    decode(&resp)
+}
+
+/// Longest wait before the next attempt, doubling with each failed one
+fn backoff_cap(attempt: u32) -> Duration {
+    BACKOFF_BASE * 2u32.pow(attempt)
+}
+
+/// Full jitter: anything up to the cap
+fn random_jitter(max: Duration) -> Duration {
+    rand::thread_rng().gen_range(Duration::ZERO..=max)
+}
+
+/// Runs `request` a few times with backoff, fails at once while the host's circuit is open
+async fn with_retries<T, F>(
+    breakers: &Mutex<HashMap<String, CircuitBreaker>>,
+    host: &str,
+    now: impl Fn() -> Instant,
+    jitter: impl Fn(Duration) -> Duration,
+    mut request: impl FnMut() -> F,
+) -> Result<T>
+where
+    F: Future<Output = Result<T>>,
+{
+    if !with_breaker(breakers, host, |breaker| breaker.allows(host, now())) {
+        return Err(anyhow!("Realtime endpoint {host} is unavailable"));
+    }
+
+    let mut attempt = 0;
+    loop {
+        match request().await {
+            Ok(result) => {
+                with_breaker(breakers, host, |breaker| breaker.succeeded(host));
+                return Ok(result);
+            }
+            Err(e) => {
+                attempt += 1;
+                if attempt == MAX_ATTEMPTS {
+                    with_breaker(breakers, host, |breaker| breaker.failed(host, now()));
+                    return Err(e);
+                }
+                log::warn!("Realtime request to {host} failed, attempt {attempt}: {e}");
+                tokio::time::sleep(jitter(backoff_cap(attempt))).await;
+            }
+        }
+    }
+}
+
+/// Fetches a GTFS-RT message, retrying a few times. Fails at once while the endpoint is known to be down.
+pub async fn fetch(url: &str) -> Result<FeedMessage> {
+    let host = reqwest::Url::parse(url)?
+        .host_str()
+        .unwrap_or_default()
+        .to_string();
+
+    with_retries(&BREAKERS, &host, Instant::now, random_jitter, || {
+        fetch_once(url)
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+
+    const HOST: &str = "example.com";
+
+    #[test]
+    fn circuit_opens_after_threshold_failures() {
+        let start = Instant::now();
+        let mut breaker = CircuitBreaker::default();
+        for _ in 1..FAILURE_THRESHOLD {
+            breaker.failed(HOST, start);
+            assert!(breaker.allows(HOST, start));
+        }
+        breaker.failed(HOST, start);
+        assert!(!breaker.allows(HOST, start));
+        assert!(!breaker.allows(HOST, start + OPEN_DURATION / 2));
+    }
+
+    #[test]
+    fn half_open_circuit_lets_a_single_probe_through() {
+        let start = Instant::now();
+        let mut breaker = CircuitBreaker::default();
+        for _ in 0..FAILURE_THRESHOLD {
+            breaker.failed(HOST, start);
+        }
+
+        let later = start + OPEN_DURATION;
+        assert!(breaker.allows(HOST, later));
+        assert!(!breaker.allows(HOST, later));
+
+        // The probe failed: closed for another OPEN_DURATION
+        breaker.failed(HOST, later);
+        assert!(!breaker.allows(HOST, later + OPEN_DURATION / 2));
+        assert!(breaker.allows(HOST, later + OPEN_DURATION));
+    }
+
+    #[test]
+    fn success_resets_the_circuit() {
+        let start = Instant::now();
+        let mut breaker = CircuitBreaker::default();
+        for _ in 0..FAILURE_THRESHOLD {
+            breaker.failed(HOST, start);
+        }
+        assert!(breaker.allows(HOST, start + OPEN_DURATION));
+        breaker.succeeded(HOST);
+
+        assert!(breaker.allows(HOST, start + OPEN_DURATION));
+        // Counting starts over
+        for _ in 1..FAILURE_THRESHOLD {
+            breaker.failed(HOST, start);
+        }
+        assert!(breaker.allows(HOST, start + OPEN_DURATION));
+    }
+
+    #[test]
+    fn empty_message_is_an_error() {
+        assert!(decode(&[]).is_err());
+
+        let mut message = FeedMessage::default();
+        message.header.gtfs_realtime_version = String::from("2.0");
+        assert_eq!(decode(&message.encode_to_vec()).unwrap(), message);
+    }
+
+    #[tokio::test]
+    async fn retries_with_growing_backoff() {
+        let breakers = Mutex::new(HashMap::new());
+        let waits = Mutex::new(vec![]);
+        let attempts = Cell::new(0);
+        let result = with_retries(
+            &breakers,
+            HOST,
+            Instant::now,
+            |max| {
+                waits.lock().unwrap().push(max);
+                Duration::ZERO
+            },
+            || {
+                attempts.set(attempts.get() + 1);
+                let attempt = attempts.get();
+                async move {
+                    if attempt < MAX_ATTEMPTS {
+                        Err(anyhow!("attempt {attempt} failed"))
+                    } else {
+                        Ok(attempt)
+                    }
+                }
+            },
+        )
+        .await;
+
+        assert_eq!(result.unwrap(), MAX_ATTEMPTS);
+        assert_eq!(*waits.lock().unwrap(), [BACKOFF_BASE * 2, BACKOFF_BASE * 4]);
+        assert_eq!(with_breaker(&breakers, HOST, |breaker| breaker.failures), 0);
+    }
+
+    #[tokio::test]
+    async fn failed_requests_open_the_circuit() {
+        let breakers = Mutex::new(HashMap::new());
+        let start = Instant::now();
+        let clock = Cell::new(start);
+        let attempts = Cell::new(0);
+        let fetch = || {
+            with_retries(
+                &breakers,
+                HOST,
+                || clock.get(),
+                |_| Duration::ZERO,
+                || {
+                    attempts.set(attempts.get() + 1);
+                    async { Err::<(), _>(anyhow!("down")) }
+                },
+            )
+        };
+
+        for _ in 0..FAILURE_THRESHOLD {
+            assert!(fetch().await.is_err());
+        }
+        assert_eq!(attempts.get(), FAILURE_THRESHOLD * MAX_ATTEMPTS);
+
+        // Open: nothing is sent
+        assert!(fetch().await.is_err());
+        assert_eq!(attempts.get(), FAILURE_THRESHOLD * MAX_ATTEMPTS);
+
+        // Half open: one more round of attempts
+        clock.set(start + OPEN_DURATION);
+        assert!(fetch().await.is_err());
+        assert_eq!(attempts.get(), (FAILURE_THRESHOLD + 1) * MAX_ATTEMPTS);
+        assert!(fetch().await.is_err());
+        assert_eq!(attempts.get(), (FAILURE_THRESHOLD + 1) * MAX_ATTEMPTS);
+    }
+}
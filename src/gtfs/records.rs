@@ -0,0 +1,375 @@
+use std::fmt::Display;
+use std::io::Read;
+
+use chrono::NaiveDate;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer};
+
+/// A row (or a whole file) that could not be loaded.
+/// Loading goes on without it, so these are only reported.
+#[derive(Debug, Clone)]
+pub struct ParseWarning {
+    pub file: &'static str,
+    pub line: u64,
+    pub message: String,
+}
+
+impl Display for ParseWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}: {}", self.file, self.line, self.message)
+    }
+}
+
+/// One line of a GTFS table. Columns are matched by header name, so their order doesn't matter
+/// and unknown columns are ignored.
+pub trait GtfsRecord: DeserializeOwned {
+    const FILE: &'static str;
+    const REQUIRED: &'static [&'static str];
+    /// Feed is valid without this file
+    const OPTIONAL: bool = false;
+
+    /// Checks the values serde takes as plain strings. The error names the bad column.
+    fn check(&self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AgencyRecord {
+    pub agency_timezone: String,
+}
+
+impl GtfsRecord for AgencyRecord {
+    const FILE: &'static str = "agency.txt";
+    const REQUIRED: &'static [&'static str] = &["agency_timezone"];
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RouteRecord {
+    pub route_id: String,
+    #[serde(default)]
+    pub route_short_name: String,
+    #[serde(default)]
+    pub route_long_name: String,
+    pub route_type: Option<u16>,
+    /// SPb specific column, holds "bus", "tram", "trolley" and so on
+    pub transport_type: Option<String>,
+}
+
+impl GtfsRecord for RouteRecord {
+    const FILE: &'static str = "routes.txt";
+    const REQUIRED: &'static [&'static str] = &["route_id"];
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StopRecord {
+    pub stop_id: String,
+    #[serde(default)]
+    pub stop_name: String,
+    pub stop_lat: Option<f64>,
+    pub stop_lon: Option<f64>,
+}
+
+impl GtfsRecord for StopRecord {
+    const FILE: &'static str = "stops.txt";
+    const REQUIRED: &'static [&'static str] = &["stop_id"];
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TripRecord {
+    pub route_id: String,
+    pub service_id: String,
+    pub trip_id: String,
+    pub direction_id: Option<u8>,
+}
+
+impl GtfsRecord for TripRecord {
+    const FILE: &'static str = "trips.txt";
+    const REQUIRED: &'static [&'static str] = &["route_id", "service_id", "trip_id"];
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StopTimeRecord {
+    pub trip_id: String,
+    pub arrival_time: Option<String>,
+    pub departure_time: Option<String>,
+    pub stop_id: String,
+    pub stop_sequence: u32,
+}
+
+impl GtfsRecord for StopTimeRecord {
+    const FILE: &'static str = "stop_times.txt";
+    const REQUIRED: &'static [&'static str] = &["trip_id", "stop_id", "stop_sequence"];
+
+    fn check(&self) -> Result<(), String> {
+        let times = [
+            ("arrival_time", &self.arrival_time),
+            ("departure_time", &self.departure_time),
+        ];
+        for (column, time) in times {
+            if let Some(time) = time {
+                if parse_time(time).is_none() {
+                    return Err(format!("{column}: invalid time {time:?}"));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CalendarRecord {
+    pub service_id: String,
+    pub monday: u8,
+    pub tuesday: u8,
+    pub wednesday: u8,
+    pub thursday: u8,
+    pub friday: u8,
+    pub saturday: u8,
+    pub sunday: u8,
+    #[serde(deserialize_with = "date")]
+    pub start_date: NaiveDate,
+    #[serde(deserialize_with = "date")]
+    pub end_date: NaiveDate,
+}
+
+impl GtfsRecord for CalendarRecord {
+    const FILE: &'static str = "calendar.txt";
+    const REQUIRED: &'static [&'static str] = &[
+        "service_id",
+        "monday",
+        "tuesday",
+        "wednesday",
+        "thursday",
+        "friday",
+        "saturday",
+        "sunday",
+        "start_date",
+        "end_date",
+    ];
+    const OPTIONAL: bool = true;
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CalendarDateRecord {
+    pub service_id: String,
+    #[serde(deserialize_with = "date")]
+    pub date: NaiveDate,
+    /// 1 - service added, 2 - service removed
+    pub exception_type: u8,
+}
+
+impl GtfsRecord for CalendarDateRecord {
+    const FILE: &'static str = "calendar_dates.txt";
+    const REQUIRED: &'static [&'static str] = &["service_id", "date", "exception_type"];
+    const OPTIONAL: bool = true;
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FeedInfoRecord {
+    pub feed_version: Option<String>,
+}
+
+impl GtfsRecord for FeedInfoRecord {
+    const FILE: &'static str = "feed_info.txt";
+    const REQUIRED: &'static [&'static str] = &[];
+    const OPTIONAL: bool = true;
+}
+
+/// GTFS dates are written as YYYYMMDD
+fn date<'de, D: Deserializer<'de>>(deserializer: D) -> Result<NaiveDate, D::Error> {
+    let s = <&str>::deserialize(deserializer)?;
+    NaiveDate::parse_from_str(s, "%Y%m%d").map_err(serde::de::Error::custom)
+}
+
+/// Reads every row of a GTFS table. Rows that fail to parse are skipped and reported in `warnings`.
+/// If a required column is missing the whole file is skipped.
+pub fn read_records<T: GtfsRecord>(data: impl Read, warnings: &mut Vec<ParseWarning>) -> Vec<T> {
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(data);
+
+    let headers = match reader.headers() {
+        Ok(headers) => headers.clone(),
+        Err(e) => {
+            warnings.push(ParseWarning {
+                file: T::FILE,
+                line: 1,
+                message: format!("failed to read header: {e}"),
+            });
+            return vec![];
+        }
+    };
+
+    let missing = T::REQUIRED
+        .iter()
+        .filter(|column| !headers.iter().any(|h| h == **column))
+        .copied()
+        .collect::<Vec<&str>>();
+    if !missing.is_empty() {
+        warnings.push(ParseWarning {
+            file: T::FILE,
+            line: 1,
+            message: format!("missing required columns {missing:?}, file skipped"),
+        });
+        return vec![];
+    }
+
+    let mut records = vec![];
+    let mut record = csv::StringRecord::new();
+    loop {
+        // csv miscounts lines on CRLF files, while records are counted right. The header is record 0 and line 1.
+        let line = reader.position().record() + 1;
+        match reader.read_record(&mut record) {
+            Ok(true) => match record.deserialize::<T>(Some(&headers)) {
+                Ok(r) => match r.check() {
+                    Ok(()) => records.push(r),
+                    Err(message) => warnings.push(ParseWarning {
+                        file: T::FILE,
+                        line,
+                        message,
+                    }),
+                },
+                Err(e) => warnings.push(ParseWarning {
+                    file: T::FILE,
+                    line,
+                    message: describe(&e),
+                }),
+            },
+            Ok(false) => break,
+            Err(e) => {
+                warnings.push(ParseWarning {
+                    file: T::FILE,
+                    line,
+                    message: describe(&e),
+                });
+                // Nothing more can be read after an I/O error
+                if e.is_io_error() {
+                    break;
+                }
+            }
+        }
+    }
+
+    records
+}
+
+/// csv adds its own (wrong for CRLF) position to deserialization errors, we report ours instead
+fn describe(e: &csv::Error) -> String {
+    match e.kind() {
+        csv::ErrorKind::Deserialize { err, .. } => err.to_string(),
+        _ => e.to_string(),
+    }
+}
+
+/// Parses GTFS time of day "H:MM:SS" into seconds. Hours may be 24 and above for trips
+/// that run past midnight.
+pub fn parse_time(time: &str) -> Option<u32> {
+    let mut parts = time.trim().split(':');
+    let h = parts.next()?.parse::<u32>().ok()?;
+    let m = parts.next()?.parse::<u32>().ok()?;
+    let s = parts.next()?.parse::<u32>().ok()?;
+    if parts.next().is_some() || m > 59 || s > 59 {
+        return None;
+    }
+    Some(h * 3600 + m * 60 + s)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn columns_matched_by_header() {
+        let data = "stop_lon,extra,stop_name,stop_id,stop_lat\n30.3,x,Невский,1,59.9\n";
+        let mut warnings = vec![];
+        let stops = read_records::<StopRecord>(data.as_bytes(), &mut warnings);
+        assert!(warnings.is_empty());
+        assert_eq!(stops.len(), 1);
+        assert_eq!(stops[0].stop_id, "1");
+        assert_eq!(stops[0].stop_name, "Невский");
+        assert_eq!(stops[0].stop_lat, Some(59.9));
+        assert_eq!(stops[0].stop_lon, Some(30.3));
+    }
+
+    #[test]
+    fn byte_order_mark_before_header() {
+        let data = "\u{feff}stop_id,stop_name\n1,Невский\n";
+        let mut warnings = vec![];
+        let stops = read_records::<StopRecord>(data.as_bytes(), &mut warnings);
+        assert!(warnings.is_empty());
+        assert_eq!(stops.len(), 1);
+        assert_eq!(stops[0].stop_id, "1");
+    }
+
+    #[test]
+    fn quoted_fields_and_crlf() {
+        let data = "route_id,route_short_name,route_long_name\r\n\
+                    1,\"3\",\"Площадь Восстания, Купчино\"\r\n\
+                    2,5,\"Сказал \"\"нет\"\"\"\r\n";
+        let mut warnings = vec![];
+        let routes = read_records::<RouteRecord>(data.as_bytes(), &mut warnings);
+        assert!(warnings.is_empty());
+        assert_eq!(routes.len(), 2);
+        assert_eq!(routes[0].route_short_name, "3");
+        assert_eq!(routes[0].route_long_name, "Площадь Восстания, Купчино");
+        assert_eq!(routes[1].route_long_name, "Сказал \"нет\"");
+        assert_eq!(routes[1].transport_type, None);
+    }
+
+    #[test]
+    fn bad_rows_reported_with_their_line() {
+        let data = "trip_id,stop_id,stop_sequence\r\nt1,1,1\r\nt1,2,second\r\nt1,3,3\r\n";
+        let mut warnings = vec![];
+        let stop_times = read_records::<StopTimeRecord>(data.as_bytes(), &mut warnings);
+        assert_eq!(stop_times.len(), 2);
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].file, "stop_times.txt");
+        assert_eq!(warnings[0].line, 3);
+    }
+
+    #[test]
+    fn bad_times_reported_with_their_column() {
+        let data = "trip_id,arrival_time,departure_time,stop_id,stop_sequence\n\
+                    t1,8:00:00,8:00:00,1,1\n\
+                    t1,,,2,2\n\
+                    t1,8:07:00,8:7x:00,3,3\n";
+        let mut warnings = vec![];
+        let stop_times = read_records::<StopTimeRecord>(data.as_bytes(), &mut warnings);
+        assert_eq!(stop_times.len(), 2);
+        assert_eq!(stop_times[1].arrival_time, None);
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].line, 4);
+        assert!(warnings[0].message.starts_with("departure_time:"));
+    }
+
+    #[test]
+    fn missing_required_column_skips_file() {
+        let data = "route_id,trip_id\nr,t\n";
+        let mut warnings = vec![];
+        let trips = read_records::<TripRecord>(data.as_bytes(), &mut warnings);
+        assert!(trips.is_empty());
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].message.contains("service_id"));
+    }
+
+    #[test]
+    fn dates() {
+        let data = "service_id,date,exception_type\ns,20260308,2\ns,2026-03-09,1\n";
+        let mut warnings = vec![];
+        let dates = read_records::<CalendarDateRecord>(data.as_bytes(), &mut warnings);
+        assert_eq!(dates.len(), 1);
+        assert_eq!(dates[0].date, NaiveDate::from_ymd_opt(2026, 3, 8).unwrap());
+        assert_eq!(warnings.len(), 1);
+    }
+
+    #[test]
+    fn times_past_midnight() {
+        assert_eq!(parse_time("8:05:00"), Some(8 * 3600 + 5 * 60));
+        assert_eq!(parse_time(" 25:30:15 "), Some(25 * 3600 + 30 * 60 + 15));
+        assert_eq!(parse_time("12:60:00"), None);
+        assert_eq!(parse_time("12:00"), None);
+        assert_eq!(parse_time("12:00:00:00"), None);
+    }
+}
@@ -0,0 +1,190 @@
+use chrono::{DateTime, Duration, NaiveDate, TimeZone};
+use chrono_tz::Tz;
+
+use super::{RouteDirection, RouteId, StaticFeed, StopId, TripId};
+
+/// A trip of the timetable calling at the stop
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScheduledArrival {
+    pub trip_id: TripId,
+    pub service_date: NaiveDate,
+    /// Unix timestamp
+    pub time: i64,
+}
+
+impl StaticFeed {
+    pub fn timezone(&self) -> Tz {
+        self.timezone.unwrap_or(Tz::UTC)
+    }
+
+    /// Service day starts at noon minus 12h. That's midnight except for the days when DST switches.
+    pub fn service_day_start(&self, date: NaiveDate) -> DateTime<Tz> {
+        let tz = self.timezone();
+        let noon = date.and_hms_opt(12, 0, 0).unwrap();
+        // Noon is never skipped nor repeated by DST switches, so there is always a single answer
+        let noon = tz
+            .from_local_datetime(&noon)
+            .earliest()
+            .unwrap_or_else(|| tz.from_utc_datetime(&noon));
+        noon - Duration::hours(12)
+    }
+
+    /// Unix timestamp of a stop time on the given service day
+    pub fn instant(&self, date: NaiveDate, time: u32) -> i64 {
+        self.service_day_start(date).timestamp() + time as i64
+    }
+
+    /// Service date of the given moment in the feed's timezone
+    pub fn date_of(&self, timestamp: i64) -> NaiveDate {
+        self.timezone()
+            .timestamp_opt(timestamp, 0)
+            .earliest()
+            .map(|t| t.date_naive())
+            .unwrap_or_default()
+    }
+
+    /// Trips of the route running on that day that stop at `stop_id` within `(from, until)`,
+    /// sorted by time.
+    pub fn arrivals(
+        &self,
+        route_id: &RouteId,
+        direction: &str,
+        stop_id: &StopId,
+        from: i64,
+        until: i64,
+    ) -> Vec<ScheduledArrival> {
+        let route = RouteDirection {
+            route_id: route_id.clone(),
+            direction: direction.to_string(),
+        };
+        let Some(visits) = self
+            .stop_visits
+            .get(stop_id)
+            .and_then(|routes| routes.get(&route))
+        else {
+            return vec![];
+        };
+
+        // Trips of the previous service day may still be on their way after midnight
+        let first_day = self.date_of(from).pred_opt().unwrap_or_default();
+        let last_day = self.date_of(until);
+
+        let mut timetable = vec![];
+
+        for day in first_day.iter_days().take_while(|day| day <= &last_day) {
+            let start = self.instant(day, 0);
+            // Visits are sorted by time, so skip straight to the window
+            let first = visits.partition_point(|visit| start + visit.time as i64 <= from);
+            for visit in visits[first..]
+                .iter()
+                .take_while(|visit| start + (visit.time as i64) < until)
+            {
+                if self.trip_is_active(&visit.trip_id, day) {
+                    timetable.push(ScheduledArrival {
+                        trip_id: visit.trip_id.clone(),
+                        service_date: day,
+                        time: start + visit.time as i64,
+                    });
+                }
+            }
+        }
+
+        timetable.sort_by_key(|arrival| arrival.time);
+        timetable
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{NaiveDateTime, TimeZone, Timelike};
+    use chrono_tz::Europe::Berlin;
+
+    use super::*;
+    use crate::gtfs::test_feed;
+
+    fn date(month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2026, month, day).unwrap()
+    }
+
+    fn local(timestamp: i64) -> NaiveDateTime {
+        Berlin.timestamp_opt(timestamp, 0).unwrap().naive_local()
+    }
+
+    fn times(arrivals: Vec<ScheduledArrival>) -> Vec<i64> {
+        arrivals.into_iter().map(|arrival| arrival.time).collect()
+    }
+
+    fn at(month: u32, day: u32, h: u32, m: u32) -> i64 {
+        Berlin
+            .from_local_datetime(&date(month, day).and_hms_opt(h, m, 0).unwrap())
+            .unwrap()
+            .timestamp()
+    }
+
+    #[test]
+    fn service_day_starts_at_midnight_on_usual_days() {
+        let feed = test_feed::sample();
+        assert_eq!(
+            feed.service_day_start(date(3, 10)).timestamp(),
+            at(3, 10, 0, 0)
+        );
+    }
+
+    #[test]
+    fn dst_days_keep_local_times() {
+        let feed = test_feed::sample();
+        // Clocks go forward on March 29 and back on October 25, 2026
+        for day in [date(3, 28), date(3, 29), date(3, 30), date(10, 25)] {
+            let time = local(feed.instant(day, 8 * 3600 + 5 * 60));
+            assert_eq!(time.date(), day);
+            assert_eq!((time.hour(), time.minute()), (8, 5), "{day}");
+        }
+        // The service day of a switch is 23 or 25 hours long, so midnight isn't its start
+        assert_eq!(
+            local(feed.service_day_start(date(3, 29)).timestamp()).hour(),
+            23
+        );
+        assert_eq!(
+            local(feed.service_day_start(date(10, 25)).timestamp()).hour(),
+            1
+        );
+    }
+
+    #[test]
+    fn times_past_midnight_belong_to_the_previous_service_day() {
+        let feed = test_feed::sample();
+        let time = local(feed.instant(date(3, 10), 25 * 3600 + 10 * 60));
+        assert_eq!(time.date(), date(3, 11));
+        assert_eq!((time.hour(), time.minute()), (1, 10));
+    }
+
+    #[test]
+    fn arrivals_of_the_route_and_direction() {
+        let feed = test_feed::sample();
+        let (route, stop) = (String::from("R1"), String::from("B"));
+
+        // Tuesday: the late trip of Monday, then the morning and noon trips
+        let arrivals = feed.arrivals(&route, "0", &stop, at(3, 10, 0, 0), at(3, 10, 23, 0));
+        assert_eq!(
+            times(arrivals),
+            vec![at(3, 10, 1, 15), at(3, 10, 8, 5), at(3, 10, 12, 5)]
+        );
+
+        let back = feed.arrivals(&route, "1", &stop, at(3, 10, 0, 0), at(3, 10, 23, 0));
+        assert_eq!(times(back), vec![at(3, 10, 9, 10)]);
+    }
+
+    #[test]
+    fn arrivals_skip_services_not_running() {
+        let feed = test_feed::sample();
+        // Saturday, the weekday trip doesn't run
+        let arrivals = feed.arrivals(
+            &String::from("R1"),
+            "0",
+            &String::from("B"),
+            at(3, 14, 6, 0),
+            at(3, 14, 23, 0),
+        );
+        assert_eq!(times(arrivals), vec![at(3, 14, 8, 5)]);
+    }
+}
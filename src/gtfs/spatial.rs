@@ -0,0 +1,147 @@
+use std::collections::HashMap;
+
+use super::StopId;
+
+/// Cell side in degrees, about a kilometer in latitude
+const CELL_DEG: f64 = 0.01;
+const METERS_PER_DEG: f64 = 111_320.0;
+const EARTH_RADIUS_M: f64 = 6_371_000.0;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Coordinates {
+    pub lat: f64,
+    pub lon: f64,
+}
+
+impl Coordinates {
+    /// Great-circle distance in meters
+    pub fn distance(&self, other: &Coordinates) -> f64 {
+        let d_lat = (other.lat - self.lat).to_radians();
+        let d_lon = (other.lon - self.lon).to_radians();
+        let a = (d_lat / 2.0).sin().powi(2)
+            + self.lat.to_radians().cos()
+                * other.lat.to_radians().cos()
+                * (d_lon / 2.0).sin().powi(2);
+        2.0 * EARTH_RADIUS_M * a.sqrt().asin()
+    }
+}
+
+/// Stops bucketed by a grid of `CELL_DEG` cells, so we only look at the cells around the point
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
+pub struct StopIndex {
+    cells: HashMap<(i32, i32), Vec<(StopId, Coordinates)>>,
+}
+
+impl StopIndex {
+    fn cell(point: &Coordinates) -> (i32, i32) {
+        (
+            (point.lat / CELL_DEG).floor() as i32,
+            (point.lon / CELL_DEG).floor() as i32,
+        )
+    }
+
+    pub fn insert(&mut self, stop_id: StopId, point: Coordinates) {
+        self.cells
+            .entry(Self::cell(&point))
+            .or_default()
+            .push((stop_id, point));
+    }
+
+    /// Up to `count` stops within `radius` meters from `point`, nearest first, with distances in meters
+    pub fn nearest(&self, point: Coordinates, count: usize, radius: f64) -> Vec<(StopId, f64)> {
+        let lat_cells = (radius / (CELL_DEG * METERS_PER_DEG)).ceil() as i32;
+        // Longitude degrees shrink towards the poles
+        let lon_cell_m = CELL_DEG * METERS_PER_DEG * point.lat.to_radians().cos().max(0.01);
+        let lon_cells = (radius / lon_cell_m).ceil() as i32;
+
+        let (lat, lon) = Self::cell(&point);
+        let mut found = vec![];
+        for lat in lat - lat_cells..=lat + lat_cells {
+            for lon in lon - lon_cells..=lon + lon_cells {
+                if let Some(stops) = self.cells.get(&(lat, lon)) {
+                    for (stop_id, stop) in stops {
+                        let distance = point.distance(stop);
+                        if distance <= radius {
+                            found.push((stop_id.clone(), distance));
+                        }
+                    }
+                }
+            }
+        }
+
+        found.sort_by(|a, b| a.1.total_cmp(&b.1));
+        found.truncate(count);
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(lat: f64, lon: f64) -> Coordinates {
+        Coordinates { lat, lon }
+    }
+
+    fn index(stops: &[(&str, f64, f64)]) -> StopIndex {
+        let mut index = StopIndex::default();
+        for (stop_id, lat, lon) in stops {
+            index.insert(StopId::from(*stop_id), point(*lat, *lon));
+        }
+        index
+    }
+
+    fn ids(found: &[(StopId, f64)]) -> Vec<&str> {
+        found.iter().map(|(stop_id, _)| stop_id.as_str()).collect()
+    }
+
+    #[test]
+    fn haversine_of_known_distances() {
+        // A degree of the equator
+        let degree = point(0.0, 0.0).distance(&point(0.0, 1.0));
+        assert!((degree - 111_195.0).abs() < 1.0, "{degree}");
+        // Paris to London
+        let paris = point(48.8566, 2.3522);
+        let london = point(51.5074, -0.1278);
+        assert!((paris.distance(&london) - 343_556.0).abs() < 10.0);
+        assert_eq!(london.distance(&london), 0.0);
+    }
+
+    #[test]
+    fn stops_across_cell_boundaries() {
+        // 60.0 and 30.0 are cell boundaries, the stops are 11 meters away from the point
+        let index = index(&[
+            ("north", 60.0001, 29.9999),
+            ("east", 59.9999, 30.0001),
+            ("north east", 60.0001, 30.0001),
+        ]);
+        let found = index.nearest(point(59.9999, 29.9999), 10, 50.0);
+        assert_eq!(ids(&found), vec!["east", "north", "north east"]);
+
+        // Cells of negative coordinates are rounded down too
+        let index = self::index(&[("west", 0.0001, -0.0001)]);
+        assert_eq!(
+            ids(&index.nearest(point(0.0001, 0.0001), 10, 50.0)),
+            vec!["west"]
+        );
+    }
+
+    #[test]
+    fn radius_cut_off() {
+        let index = index(&[
+            ("489 m", 59.9544, 30.3),
+            ("511 m", 59.9546, 30.3),
+            // Two cells east
+            ("1336 m", 59.95, 30.324),
+        ]);
+        let center = point(59.95, 30.3);
+        assert_eq!(ids(&index.nearest(center, 10, 500.0)), vec!["489 m"]);
+        let found = index.nearest(center, 10, 1500.0);
+        assert_eq!(ids(&found), vec!["489 m", "511 m", "1336 m"]);
+        assert!((found[2].1 - 1336.0).abs() < 1.0);
+        assert_eq!(
+            ids(&index.nearest(center, 2, 1500.0)),
+            vec!["489 m", "511 m"]
+        );
+    }
+}
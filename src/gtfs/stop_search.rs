@@ -0,0 +1,145 @@
+use super::{StaticFeed, StopId};
+
+/// Lowercase letters and digits only, ё is е, words separated by a single space
+pub fn normalize(name: &str) -> String {
+    name.to_lowercase()
+        .replace('ё', "е")
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+/// Least number of edits turning `pattern` into some part of `text`
+fn substring_distance(pattern: &[char], text: &[char]) -> usize {
+    // Sellers algorithm: Levenshtein where a match may start anywhere in the text
+    let mut prev: Vec<usize> = (0..=pattern.len()).collect();
+    let mut best = prev[pattern.len()];
+    for t in text {
+        let mut cur = vec![0; pattern.len() + 1];
+        for (i, p) in pattern.iter().enumerate() {
+            let substitution = prev[i] + usize::from(p != t);
+            cur[i + 1] = substitution.min(prev[i + 1] + 1).min(cur[i] + 1);
+        }
+        best = best.min(cur[pattern.len()]);
+        prev = cur;
+    }
+    best
+}
+
+impl StaticFeed {
+    /// Stops whose names look like `query`, best matches first. Stops sharing a name are returned once.
+    pub fn search_stops(&self, query: &str, count: usize) -> Vec<StopId> {
+        let query = normalize(query);
+        if query.is_empty() {
+            return vec![];
+        }
+        let pattern = query.chars().collect::<Vec<char>>();
+        // One typo per four letters
+        let max_typos = pattern.len() / 4;
+
+        let mut candidates = vec![];
+        for (name, stop_ids) in &self.stop_names {
+            let score = match name.find(&query) {
+                Some(position) => (0, position),
+                None => {
+                    let typos = substring_distance(&pattern, &name.chars().collect::<Vec<char>>());
+                    if typos > max_typos {
+                        continue;
+                    }
+                    (typos, usize::MAX)
+                }
+            };
+            // Keep the same stop every time
+            if let Some(stop_id) = stop_ids.first() {
+                candidates.push((score, name, stop_id));
+            }
+        }
+
+        candidates.sort_by(|(a, a_name, _), (b, b_name, _)| {
+            (a, a_name.len(), a_name).cmp(&(b, b_name.len(), b_name))
+        });
+        candidates
+            .into_iter()
+            .take(count)
+            .map(|(_, _, stop_id)| stop_id.clone())
+            .collect()
+    }
+
+    /// All stops named the same as `stop_id`, usually on both sides of a street
+    pub fn stops_with_same_name(&self, stop_id: &StopId) -> Vec<StopId> {
+        let Some(stop) = self.stops.get(stop_id) else {
+            return vec![];
+        };
+        self.stop_names
+            .get(&normalize(&stop.name))
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gtfs::test_feed;
+
+    fn feed() -> StaticFeed {
+        test_feed::parse(&[
+            (
+                "agency.txt",
+                "agency_name,agency_url,agency_timezone\nTest,http://example.com,Europe/Moscow\n",
+            ),
+            (
+                "stops.txt",
+                "stop_id,stop_name\n\
+                 1,Невский проспект\n\
+                 2,НЕВСКИЙ ПРОСПЕКТ\n\
+                 3,\"Ст. метро \"\"Петроградская\"\"\"\n\
+                 4,Улица Ёлкина\n\
+                 5,Невская улица\n",
+            ),
+        ])
+    }
+
+    #[test]
+    fn names_normalized() {
+        assert_eq!(
+            normalize("Ст. метро «Петроградская»"),
+            "ст метро петроградская"
+        );
+        assert_eq!(normalize("  Ёлкина  ул.,  д 5"), "елкина ул д 5");
+    }
+
+    #[test]
+    fn yo_case_and_punctuation_ignored() {
+        let feed = feed();
+        assert_eq!(feed.search_stops("ёлкина", 5), vec!["4"]);
+        assert_eq!(feed.search_stops("улица елкина", 5), vec!["4"]);
+        assert_eq!(feed.search_stops("метро петроградская", 5), vec!["3"]);
+        assert_eq!(feed.search_stops("...", 5), Vec::<StopId>::new());
+    }
+
+    #[test]
+    fn stops_sharing_a_name_found_once() {
+        let feed = feed();
+        assert_eq!(feed.search_stops("невск", 5), vec!["5", "1"]);
+        assert_eq!(
+            feed.stops_with_same_name(&StopId::from("2")),
+            vec!["1", "2"]
+        );
+    }
+
+    #[test]
+    fn typos_allowed_one_per_four_letters() {
+        let feed = feed();
+        // 7 letters, one typo allowed
+        assert_eq!(feed.search_stops("нивский", 5), vec!["1"]);
+        assert!(feed.search_stops("нивскей", 5).is_empty());
+        // 3 letters, no typos allowed
+        assert_eq!(feed.search_stops("нев", 5).len(), 2);
+        assert!(feed.search_stops("нив", 5).is_empty());
+        // 9 letters, two typos allowed
+        assert_eq!(feed.search_stops("питраград", 5), vec!["3"]);
+        assert!(feed.search_stops("питраграт", 5).is_empty());
+    }
+}
@@ -0,0 +1,80 @@
+//! Small feeds for tests, built from GTFS tables the way real archives are parsed
+
+use std::io::{Cursor, Write};
+
+use super::StaticFeed;
+
+/// Zip archive of the given `(file name, contents)` tables
+pub fn archive(tables: &[(&str, &str)]) -> Vec<u8> {
+    let mut zip = zip::ZipWriter::new(Cursor::new(vec![]));
+    for (name, contents) in tables {
+        zip.start_file(*name, zip::write::FileOptions::default())
+            .unwrap();
+        zip.write_all(contents.as_bytes()).unwrap();
+    }
+    zip.finish().unwrap().into_inner()
+}
+
+pub fn parse(tables: &[(&str, &str)]) -> StaticFeed {
+    let archive = zip::ZipArchive::new(Cursor::new(archive(tables))).unwrap();
+    super::parse_feed(archive).unwrap()
+}
+
+pub fn sample() -> StaticFeed {
+    parse(SAMPLE)
+}
+
+/// Tram R1 going A - B - C - D and back, every day of 2026 in Berlin time. `f2` runs past
+/// midnight, `x1` doesn't run on weekends.
+pub const SAMPLE: &[(&str, &str)] = &[
+    (
+        "agency.txt",
+        "agency_name,agency_url,agency_timezone\nTest,http://example.com,Europe/Berlin\n",
+    ),
+    (
+        "routes.txt",
+        "route_id,route_short_name,route_long_name,route_type\nR1,1,A - D,0\n",
+    ),
+    (
+        "stops.txt",
+        "stop_id,stop_name,stop_lat,stop_lon\n\
+             A,Alpha,52.50,13.40\n\
+             B,Beta,52.51,13.40\n\
+             C,Gamma,52.52,13.40\n\
+             D,Delta,52.53,13.40\n",
+    ),
+    (
+        "trips.txt",
+        "route_id,service_id,trip_id,direction_id\n\
+             R1,daily,f1,0\n\
+             R1,daily,f2,0\n\
+             R1,weekdays,x1,0\n\
+             R1,daily,b1,1\n",
+    ),
+    (
+        "stop_times.txt",
+        "trip_id,arrival_time,departure_time,stop_id,stop_sequence\n\
+             f1,08:00:00,08:00:00,A,1\n\
+             f1,08:05:00,08:05:00,B,2\n\
+             f1,08:10:00,08:10:00,C,3\n\
+             f1,08:15:00,08:15:00,D,4\n\
+             f2,25:10:00,25:10:00,A,1\n\
+             f2,25:15:00,25:15:00,B,2\n\
+             f2,25:20:00,25:20:00,C,3\n\
+             f2,25:25:00,25:25:00,D,4\n\
+             x1,12:00:00,12:00:00,A,1\n\
+             x1,12:05:00,12:05:00,B,2\n\
+             x1,12:10:00,12:10:00,C,3\n\
+             x1,12:15:00,12:15:00,D,4\n\
+             b1,09:00:00,09:00:00,D,1\n\
+             b1,09:05:00,09:05:00,C,2\n\
+             b1,09:10:00,09:10:00,B,3\n\
+             b1,09:15:00,09:15:00,A,4\n",
+    ),
+    (
+        "calendar.txt",
+        "service_id,monday,tuesday,wednesday,thursday,friday,saturday,sunday,start_date,end_date\n\
+             daily,1,1,1,1,1,1,1,20260101,20261231\n\
+             weekdays,1,1,1,1,1,0,0,20260101,20261231\n",
+    ),
+];
@@ -0,0 +1,185 @@
+use gtfs_rt::FeedMessage;
+
+use super::{Coordinates, RouteId, StaticFeed, StopId};
+
+/// A vehicle of the route as seen by the VehiclePositions feed
+#[derive(Debug, Clone, PartialEq)]
+pub struct VehicleOnRoute {
+    pub vehicle_id: Option<String>,
+    /// Board number or whatever the operator shows to passengers
+    pub label: Option<String>,
+    pub position: Coordinates,
+    /// Stop of the route pattern the vehicle is at or closest to
+    pub nearest_stop: Option<StopId>,
+    /// Stops left to the user's stop, `None` if the vehicle has already passed it
+    pub stops_away: Option<usize>,
+    /// Unix timestamp of the position
+    pub timestamp: Option<u64>,
+}
+
+impl StaticFeed {
+    /// Vehicles of `message` going along the route in `direction`, the ones closest to `stop_id` first
+    pub fn vehicles_on_route(
+        &self,
+        message: &FeedMessage,
+        route_id: &RouteId,
+        direction: &str,
+        stop_id: &StopId,
+    ) -> Vec<VehicleOnRoute> {
+        let pattern = self
+            .route_stops(route_id, direction)
+            .cloned()
+            .unwrap_or_default();
+        let user_stop = pattern.iter().position(|id| id == stop_id);
+
+        let mut vehicles = vec![];
+
+        for entity in &message.entity {
+            let Some(vehicle) = &entity.vehicle else {
+                continue;
+            };
+            let Some(position) = &vehicle.position else {
+                continue;
+            };
+            let trip = vehicle.trip.clone().unwrap_or_default();
+            // The feed is requested per route, so a vehicle without route belongs to ours
+            if !self.trip_matches(&trip, Some(route_id), route_id, direction, stop_id) {
+                continue;
+            }
+            let position = Coordinates {
+                lat: position.latitude as f64,
+                lon: position.longitude as f64,
+            };
+
+            // Operator's own idea of the stop is better than the geometry
+            let nearest = vehicle
+                .stop_id
+                .as_ref()
+                .and_then(|id| pattern.iter().position(|stop| stop == id))
+                .or_else(|| self.nearest_pattern_stop(&pattern, &position));
+
+            let stops_away = match (nearest, user_stop) {
+                (Some(nearest), Some(user_stop)) => user_stop.checked_sub(nearest),
+                _ => None,
+            };
+
+            vehicles.push(VehicleOnRoute {
+                vehicle_id: vehicle.vehicle.as_ref().and_then(|v| v.id.clone()),
+                label: vehicle.vehicle.as_ref().and_then(|v| v.label.clone()),
+                position,
+                nearest_stop: nearest.map(|i| pattern[i].clone()),
+                stops_away,
+                timestamp: vehicle.timestamp,
+            });
+        }
+
+        // Approaching vehicles first, nearest of them first
+        vehicles.sort_by_key(|v| v.stops_away.unwrap_or(usize::MAX));
+        vehicles
+    }
+
+    /// Index of the pattern stop closest to the point
+    fn nearest_pattern_stop(&self, pattern: &[StopId], point: &Coordinates) -> Option<usize> {
+        pattern
+            .iter()
+            .enumerate()
+            .filter_map(|(i, id)| {
+                let stop = self.stops.get(id)?.coordinates?;
+                Some((i, point.distance(&stop)))
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(i, _)| i)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use gtfs_rt::{FeedEntity, Position, TripDescriptor, VehicleDescriptor, VehiclePosition};
+
+    use super::*;
+    use crate::gtfs::test_feed;
+
+    fn vehicle(id: &str, trip_id: Option<&str>, stop_id: Option<&str>, lat: f32) -> FeedEntity {
+        FeedEntity {
+            id: id.to_string(),
+            vehicle: Some(VehiclePosition {
+                trip: Some(TripDescriptor {
+                    trip_id: trip_id.map(String::from),
+                    direction_id: Some(0),
+                    ..Default::default()
+                }),
+                vehicle: Some(VehicleDescriptor {
+                    id: Some(id.to_string()),
+                    ..Default::default()
+                }),
+                position: Some(Position {
+                    latitude: lat,
+                    longitude: 13.40,
+                    ..Default::default()
+                }),
+                stop_id: stop_id.map(String::from),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn on_route(entities: Vec<FeedEntity>, direction: &str, stop_id: &str) -> Vec<VehicleOnRoute> {
+        let message = FeedMessage {
+            entity: entities,
+            ..Default::default()
+        };
+        test_feed::sample().vehicles_on_route(
+            &message,
+            &String::from("R1"),
+            direction,
+            &stop_id.to_string(),
+        )
+    }
+
+    #[test]
+    fn counts_stops_left_to_the_user() {
+        let vehicles = on_route(
+            vec![
+                vehicle("far", Some("f1"), Some("A"), 52.50),
+                vehicle("near", Some("f2"), Some("C"), 52.52),
+            ],
+            "0",
+            "D",
+        );
+        let found = vehicles
+            .iter()
+            .map(|v| (v.vehicle_id.as_deref().unwrap(), v.stops_away))
+            .collect::<Vec<_>>();
+        assert_eq!(found, vec![("near", Some(1)), ("far", Some(3))]);
+    }
+
+    #[test]
+    fn vehicles_gone_past_the_stop_come_last() {
+        let vehicles = on_route(
+            vec![
+                vehicle("past", Some("f1"), Some("C"), 52.52),
+                vehicle("coming", Some("f2"), Some("A"), 52.50),
+            ],
+            "0",
+            "B",
+        );
+        assert_eq!(vehicles[0].vehicle_id.as_deref(), Some("coming"));
+        assert_eq!(vehicles[0].stops_away, Some(1));
+        assert_eq!(vehicles[1].vehicle_id.as_deref(), Some("past"));
+        assert_eq!(vehicles[1].stops_away, None);
+    }
+
+    #[test]
+    fn nearest_stop_by_position_without_stop_id() {
+        let vehicles = on_route(vec![vehicle("1", None, None, 52.511)], "0", "D");
+        assert_eq!(vehicles[0].nearest_stop.as_deref(), Some("B"));
+        assert_eq!(vehicles[0].stops_away, Some(2));
+    }
+
+    #[test]
+    fn other_direction_is_left_out() {
+        let vehicles = on_route(vec![vehicle("1", Some("b1"), Some("C"), 52.52)], "0", "D");
+        assert!(vehicles.is_empty());
+    }
+}
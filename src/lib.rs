@@ -0,0 +1,67 @@
+mod alert_notifier;
+mod config;
+mod feed_cache;
+mod feed_updater;
+mod forecast_hub;
+pub mod gtfs;
+mod saved_routes_db;
+mod scheduler;
+mod searches_db;
+mod tg_bot;
+mod transit;
+
+use std::sync::Arc;
+
+use crate::config::CONFIG;
+use crate::transit::{Cities, CityTransit, FixtureTransit, Transit};
+
+/// Loads the feeds and runs the bot until it is stopped
+pub async fn run() {
+    if let Err(e) = config::load() {
+        log::error!("Invalid configuration: {e:#}");
+        std::process::exit(1);
+    }
+    lazy_static::initialize(&saved_routes_db::DB);
+    lazy_static::initialize(&searches_db::DB);
+
+    if let Some(dir) = &CONFIG.transit_fixture {
+        let fixture = match FixtureTransit::load(dir) {
+            Ok(fixture) => fixture,
+            Err(e) => {
+                log::error!("Invalid fixture in {}: {e:#}", dir.display());
+                std::process::exit(1);
+            }
+        };
+        log::warn!("Running offline on the fixture in {}", dir.display());
+        tg_bot::bot(Cities::new(vec![Arc::new(fixture)])).await;
+        return;
+    }
+
+    let mut cities: Vec<Transit> = vec![];
+    for config in &CONFIG.cities {
+        let city = Arc::new(CityTransit::new(config.clone()));
+        match feed_cache::load(&config.id) {
+            Ok(feed) => {
+                city.set_feed(feed).await;
+                log::warn!("Feed of {} loaded from cache", config.id);
+                // The cached feed serves while we check for a fresh one
+                let city = city.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = feed_updater::update(&city).await {
+                        log::error!(
+                            "Failed to update static feed of {}, keeping the cached one: {e}",
+                            city.config.id
+                        );
+                    }
+                });
+            }
+            Err(e) => {
+                log::warn!("No usable feed cache of {}: {e}", config.id);
+                feed_updater::first_update(&city).await;
+            }
+        }
+        tokio::spawn(feed_updater::run(city.clone()));
+        cities.push(city);
+    }
+    tg_bot::bot(Cities::new(cities)).await;
+}
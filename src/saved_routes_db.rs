@@ -1,22 +1,67 @@
+use crate::config::CONFIG;
+use crate::gtfs::{RouteDirection, RouteId, StopId};
+use crate::scheduler::Schedules;
 use crate::tg_bot::{SavedRouteData, SavedRouteName, SavedRoutes};
 use anyhow::{Ok, Result};
+use lazy_static::lazy_static;
+use std::collections::HashMap;
 use teloxide::types::ChatId;
 
+lazy_static! {
+    /// sled locks the database for the whole process, so it's opened once and shared
+    pub static ref DB: sled::Db = sled::Config::new()
+        .path("db/saved_routes")
+        .cache_capacity(100_000_000)
+        .open()
+        .expect("Failed to open db/saved_routes");
+}
+
 pub trait SavedRoutesDb {
     fn get_saved_routes(&self) -> Result<SavedRoutes>;
     fn add_route_to_saved(&mut self, name: SavedRouteName, data: SavedRouteData) -> Result<()>;
     fn remove_route_from_saved(&mut self, name: &SavedRouteName) -> Result<()>;
+    fn get_city(&self) -> Result<Option<String>>;
+    fn set_city(&mut self, city: &str) -> Result<()>;
+    fn get_schedules(&self) -> Result<Schedules>;
+    fn set_schedules(&mut self, schedules: &Schedules) -> Result<()>;
+}
+
+/// Saved route as stored before cities were added
+#[derive(serde::Deserialize)]
+struct LegacySavedRouteData {
+    route_id: RouteId,
+    stop_id: StopId,
+    direction: String,
+    leeway: u64,
+}
+
+/// Routes written before cities were added belong to the default city
+fn decode_routes(bytes: &[u8]) -> Result<SavedRoutes> {
+    bincode::deserialize::<SavedRoutes>(bytes).or_else(|_| {
+        let legacy = bincode::deserialize::<HashMap<SavedRouteName, LegacySavedRouteData>>(bytes)?;
+        Ok(legacy
+            .into_iter()
+            .map(|(name, data)| {
+                let data = SavedRouteData {
+                    city: CONFIG.default_city().to_string(),
+                    routes: vec![RouteDirection {
+                        route_id: data.route_id,
+                        direction: data.direction,
+                    }],
+                    stop_id: data.stop_id,
+                    leeway: data.leeway,
+                };
+                (name, data)
+            })
+            .collect())
+    })
 }
 
 impl SavedRoutesDb for ChatId {
     fn get_saved_routes(&self) -> Result<SavedRoutes> {
         log::warn!("Getting saved routes for chat ID {}", self.0);
-        let db = sled::Config::new()
-            .path("db/saved_routes")
-            .cache_capacity(100_000_000)
-            .open()?;
-        if let Some(ivec) = db.get(bincode::serialize(&self.0)?)? {
-            let routes = bincode::deserialize::<SavedRoutes>(&ivec)?;
+        if let Some(ivec) = DB.get(bincode::serialize(&self.0)?)? {
+            let routes = decode_routes(&ivec)?;
             log::warn!("Saved routes: {:#?}", routes);
             return Ok(routes);
         }
@@ -32,36 +77,87 @@ impl SavedRoutesDb for ChatId {
             name,
             data
         );
-        let db = sled::Config::new()
-            .path("db/saved_routes")
-            .cache_capacity(100_000_000)
-            .open()?;
         let key = bincode::serialize(&self.0)?;
         let mut routes;
-        if let Some(ivec) = db.get(&key)? {
-            routes = bincode::deserialize::<SavedRoutes>(&ivec)?;
+        if let Some(ivec) = DB.get(&key)? {
+            routes = decode_routes(&ivec)?;
         } else {
             routes = SavedRoutes::new();
         }
         routes.insert(name, data);
-        db.insert(key, bincode::serialize(&routes)?)?;
+        DB.insert(key, bincode::serialize(&routes)?)?;
         log::warn!("Added succesfully");
         Ok(())
     }
 
     fn remove_route_from_saved(&mut self, name: &SavedRouteName) -> Result<()> {
         log::warn!("Remove route {name} from chat ID {}", self.0);
-        let db = sled::Config::new()
-            .path("db/saved_routes")
-            .cache_capacity(100_000_000)
-            .open()?;
         let key = bincode::serialize(&self.0)?;
-        if let Some(ivec) = db.get(&key)? {
-            let mut routes = bincode::deserialize::<SavedRoutes>(&ivec)?;
+        if let Some(ivec) = DB.get(&key)? {
+            let mut routes = decode_routes(&ivec)?;
             routes.remove(name);
-            db.insert(key, bincode::serialize(&routes)?)?;
+            DB.insert(&key, bincode::serialize(&routes)?)?;
+        }
+        // Schedules of the route have nothing to start anymore
+        let schedules = DB.open_tree("schedules")?;
+        if let Some(ivec) = schedules.get(&key)? {
+            let mut remaining = bincode::deserialize::<Schedules>(&ivec)?;
+            remaining.retain(|schedule| &schedule.route != name);
+            schedules.insert(key, bincode::serialize(&remaining)?)?;
         }
         log::warn!("Removed successfully");
         Ok(())
     }
+
+    fn get_city(&self) -> Result<Option<String>> {
+        let cities = DB.open_tree("cities")?;
+        match cities.get(bincode::serialize(&self.0)?)? {
+            Some(ivec) => Ok(Some(String::from_utf8(ivec.to_vec())?)),
+            None => Ok(None),
+        }
+    }
+
+    fn set_city(&mut self, city: &str) -> Result<()> {
+        log::warn!("Chat ID {} is in {city} now", self.0);
+        let cities = DB.open_tree("cities")?;
+        cities.insert(bincode::serialize(&self.0)?, city.as_bytes())?;
+        Ok(())
+    }
+
+    fn get_schedules(&self) -> Result<Schedules> {
+        let schedules = DB.open_tree("schedules")?;
+        match schedules.get(bincode::serialize(&self.0)?)? {
+            Some(ivec) => Ok(bincode::deserialize(&ivec)?),
+            None => Ok(Schedules::new()),
+        }
+    }
+
+    fn set_schedules(&mut self, schedules: &Schedules) -> Result<()> {
+        log::warn!("Set schedules for chat ID {}: {:#?}", self.0, schedules);
+        DB.open_tree("schedules")?
+            .insert(bincode::serialize(&self.0)?, bincode::serialize(schedules)?)?;
+        Ok(())
+    }
+}
+
+/// Saved routes of every chat
+pub fn all_saved_routes() -> Result<Vec<(ChatId, SavedRoutes)>> {
+    let mut all = vec![];
+    for entry in DB.iter() {
+        let (key, value) = entry?;
+        let chat_id = bincode::deserialize::<i64>(&key)?;
+        all.push((ChatId(chat_id), decode_routes(&value)?));
+    }
+    Ok(all)
+}
+
+/// Schedules of every chat
+pub fn all_schedules() -> Result<Vec<(ChatId, Schedules)>> {
+    let mut all = vec![];
+    for entry in DB.open_tree("schedules")?.iter() {
+        let (key, value) = entry?;
+        let chat_id = bincode::deserialize::<i64>(&key)?;
+        all.push((ChatId(chat_id), bincode::deserialize(&value)?));
+    }
+    Ok(all)
 }
@@ -0,0 +1,309 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use anyhow::Result;
+use chrono::{Datelike, NaiveDate, NaiveDateTime, NaiveTime, Utc, Weekday};
+use teloxide::prelude::*;
+use teloxide::types::MessageId;
+
+use crate::config::CONFIG;
+use crate::saved_routes_db::{self, SavedRoutesDb};
+use crate::tg_bot::{self, SavedRouteName, SavedRoutes};
+use crate::transit::Cities;
+
+/// Schedules are set to the minute
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+pub const WEEK: [Weekday; 7] = [
+    Weekday::Mon,
+    Weekday::Tue,
+    Weekday::Wed,
+    Weekday::Thu,
+    Weekday::Fri,
+    Weekday::Sat,
+    Weekday::Sun,
+];
+
+/// Starts a search of a saved route on the chosen days, in the time zone of the route's city
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Schedule {
+    pub id: u32,
+    pub route: SavedRouteName,
+    pub days: Vec<Weekday>,
+    /// The search starts at `start` and is stopped at `end` if it's still running. A window
+    /// ending before it starts runs past midnight.
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+    pub skip_holidays: bool,
+    pub paused: bool,
+    /// Day of the last search started
+    pub last_run: Option<NaiveDate>,
+    /// Status message of the search started by the schedule, while it's running
+    pub search: Option<i32>,
+}
+
+pub type Schedules = Vec<Schedule>;
+
+impl Schedule {
+    /// Day the window around `now` started on, `None` outside of the window
+    fn window_start(&self, now: NaiveDateTime) -> Option<NaiveDate> {
+        let (today, time) = (now.date(), now.time());
+        if self.start <= self.end {
+            (self.start <= time && time < self.end).then_some(today)
+        } else if self.start <= time {
+            Some(today)
+        } else if time < self.end {
+            today.pred_opt()
+        } else {
+            None
+        }
+    }
+
+    /// Whether the search should start now. The days and holidays are the ones the window
+    /// starts on.
+    fn is_due(&self, now: NaiveDateTime, is_holiday: impl Fn(NaiveDate) -> bool) -> bool {
+        let Some(day) = self.window_start(now) else {
+            return false;
+        };
+        !self.paused
+            && self.last_run != Some(day)
+            && self.days.contains(&day.weekday())
+            && !(self.skip_holidays && is_holiday(day))
+    }
+
+    /// Whether the search started by the schedule has outlived its window
+    fn is_over(&self, now: NaiveDateTime) -> bool {
+        self.search.is_some() && self.window_start(now) != self.last_run
+    }
+
+    /// Like "⏸Работа: по будням 08:10-08:40, кроме праздников"
+    pub fn describe(&self) -> String {
+        let mut text = String::new();
+        if self.paused {
+            text += "⏸";
+        }
+        text += &format!(
+            "{}: {} {}-{}",
+            self.route,
+            days_text(&self.days),
+            self.start.format("%H:%M"),
+            self.end.format("%H:%M")
+        );
+        if self.skip_holidays {
+            text += ", кроме праздников";
+        }
+        text
+    }
+}
+
+pub fn day_name(day: Weekday) -> &'static str {
+    match day {
+        Weekday::Mon => "пн",
+        Weekday::Tue => "вт",
+        Weekday::Wed => "ср",
+        Weekday::Thu => "чт",
+        Weekday::Fri => "пт",
+        Weekday::Sat => "сб",
+        Weekday::Sun => "вс",
+    }
+}
+
+fn days_text(days: &[Weekday]) -> String {
+    if WEEK.iter().all(|day| days.contains(day)) {
+        return String::from("каждый день");
+    }
+    if days.len() == 5 && WEEK[..5].iter().all(|day| days.contains(day)) {
+        return String::from("по будням");
+    }
+    WEEK.iter()
+        .filter(|day| days.contains(day))
+        .map(|day| day_name(*day))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Days off are the city's fixed public holidays and the `extra` ones
+fn is_holiday(date: NaiveDate, fixed: &[(u32, u32)], extra: &[NaiveDate]) -> bool {
+    fixed.contains(&(date.month(), date.day())) || extra.contains(&date)
+}
+
+/// Starts and stops the searches of every chat's schedules
+pub async fn run(bot: Bot, cities: Cities) {
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        let all = match saved_routes_db::all_schedules() {
+            Ok(all) => all,
+            Err(e) => {
+                log::error!("Failed to get schedules: {e}");
+                continue;
+            }
+        };
+        let mut routes = match saved_routes_db::all_saved_routes() {
+            Ok(routes) => routes.into_iter().collect::<HashMap<_, _>>(),
+            Err(e) => {
+                log::error!("Failed to get saved routes: {e}");
+                continue;
+            }
+        };
+        for (chat_id, schedules) in all {
+            let routes = routes.remove(&chat_id).unwrap_or_default();
+            if let Err(e) = check(&bot, &cities, chat_id, schedules, routes).await {
+                log::error!("Failed to run schedules of chat ID {chat_id}: {e}");
+            }
+        }
+    }
+}
+
+async fn check(
+    bot: &Bot,
+    cities: &Cities,
+    chat_id: ChatId,
+    schedules: Schedules,
+    routes: SavedRoutes,
+) -> Result<()> {
+    for schedule in schedules {
+        let Some(route) = routes.get(&schedule.route) else {
+            continue;
+        };
+        let transit = cities.get(Some(&route.city));
+        let tz = transit.feed().await.timezone();
+        let now = Utc::now().with_timezone(&tz).naive_local();
+        let fixed = CONFIG
+            .city(&route.city)
+            .map_or(&[][..], |city| &city.fixed_holidays);
+        let is_holiday = |date| is_holiday(date, fixed, &CONFIG.holidays);
+
+        if schedule.is_over(now) {
+            if let Some(bot_msg) = schedule.search {
+                log::warn!("Schedule {} of chat ID {chat_id} is over", schedule.id);
+                tg_bot::cancel_search(
+                    bot,
+                    chat_id,
+                    MessageId(bot_msg),
+                    "⌛️Время по расписанию вышло, поиск остановлен",
+                )
+                .await?;
+            }
+            update(chat_id, schedule.id, |schedule| schedule.search = None)?;
+        } else if schedule.is_due(now, is_holiday) {
+            log::warn!("Schedule {} of chat ID {chat_id} is due", schedule.id);
+            // Marked first, so a search that fails to start isn't retried all the window long
+            update(chat_id, schedule.id, |schedule| {
+                schedule.last_run = schedule.window_start(now)
+            })?;
+            let bot_msg =
+                tg_bot::start_scheduled_search(bot, chat_id, transit, &schedule.route, route)
+                    .await?;
+            update(chat_id, schedule.id, |schedule| {
+                schedule.search = bot_msg.map(|bot_msg| bot_msg.0)
+            })?;
+        }
+    }
+    Ok(())
+}
+
+/// Changes the stored schedule, re-read so that edits made by the user meanwhile are kept
+fn update(mut chat_id: ChatId, id: u32, change: impl FnOnce(&mut Schedule)) -> Result<()> {
+    let mut schedules = chat_id.get_schedules()?;
+    if let Some(schedule) = schedules.iter_mut().find(|schedule| schedule.id == id) {
+        change(schedule);
+        chat_id.set_schedules(&schedules)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(day: u32, hour: u32, min: u32) -> NaiveDateTime {
+        // 2026-03-02 is a Monday
+        NaiveDate::from_ymd_opt(2026, 3, day)
+            .unwrap()
+            .and_hms_opt(hour, min, 0)
+            .unwrap()
+    }
+
+    fn schedule(start: (u32, u32), end: (u32, u32)) -> Schedule {
+        Schedule {
+            id: 1,
+            route: String::from("Работа"),
+            days: WEEK[..5].to_vec(),
+            start: NaiveTime::from_hms_opt(start.0, start.1, 0).unwrap(),
+            end: NaiveTime::from_hms_opt(end.0, end.1, 0).unwrap(),
+            skip_holidays: true,
+            paused: false,
+            last_run: None,
+            search: None,
+        }
+    }
+
+    fn no_holidays(_: NaiveDate) -> bool {
+        false
+    }
+
+    #[test]
+    fn due_once_a_day_within_the_window() {
+        let mut schedule = schedule((8, 10), (8, 40));
+        assert!(!schedule.is_due(at(2, 8, 9), no_holidays));
+        assert!(schedule.is_due(at(2, 8, 10), no_holidays));
+        assert!(!schedule.is_due(at(2, 8, 40), no_holidays));
+        // Saturday
+        assert!(!schedule.is_due(at(7, 8, 20), no_holidays));
+
+        schedule.last_run = Some(at(2, 0, 0).date());
+        assert!(!schedule.is_due(at(2, 8, 20), no_holidays));
+        assert!(schedule.is_due(at(3, 8, 20), no_holidays));
+
+        schedule.paused = true;
+        assert!(!schedule.is_due(at(3, 8, 20), no_holidays));
+    }
+
+    #[test]
+    fn search_is_over_after_the_window() {
+        let mut schedule = schedule((8, 10), (8, 40));
+        schedule.last_run = Some(at(2, 0, 0).date());
+        assert!(!schedule.is_over(at(2, 8, 50)));
+
+        schedule.search = Some(10);
+        assert!(!schedule.is_over(at(2, 8, 39)));
+        assert!(schedule.is_over(at(2, 8, 40)));
+        // Left behind by a restart on the day before
+        assert!(schedule.is_over(at(3, 8, 20)));
+    }
+
+    #[test]
+    fn window_past_midnight() {
+        let mut schedule = schedule((23, 30), (0, 30));
+        // Friday night counts, Saturday night doesn't
+        assert!(schedule.is_due(at(6, 23, 45), no_holidays));
+        assert!(schedule.is_due(at(7, 0, 15), no_holidays));
+        assert!(!schedule.is_due(at(7, 23, 45), no_holidays));
+        assert!(!schedule.is_due(at(6, 0, 45), no_holidays));
+
+        schedule.last_run = Some(at(6, 0, 0).date());
+        schedule.search = Some(10);
+        assert!(!schedule.is_due(at(7, 0, 15), no_holidays));
+        assert!(!schedule.is_over(at(7, 0, 15)));
+        assert!(schedule.is_over(at(7, 0, 30)));
+    }
+
+    #[test]
+    fn holidays_are_skipped_if_asked() {
+        let extra = [at(3, 0, 0).date()];
+        let fixed = [(3, 2)];
+        let is_holiday = |date| is_holiday(date, &fixed, &extra);
+        assert!(is_holiday(at(2, 0, 0).date()));
+        assert!(is_holiday(at(3, 0, 0).date()));
+        assert!(!is_holiday(at(4, 0, 0).date()));
+
+        let mut schedule = schedule((8, 10), (8, 40));
+        assert!(!schedule.is_due(at(2, 8, 20), is_holiday));
+        assert!(!schedule.is_due(at(3, 8, 20), is_holiday));
+        assert!(schedule.is_due(at(4, 8, 20), is_holiday));
+
+        schedule.skip_holidays = false;
+        assert!(schedule.is_due(at(2, 8, 20), is_holiday));
+    }
+}
@@ -0,0 +1,202 @@
+use crate::tg_bot::{FinishedSearch, SearchData};
+use anyhow::Result;
+use lazy_static::lazy_static;
+use teloxide::types::{ChatId, MessageId};
+
+/// Searches are keyed by chat and status message
+fn key(chat_id: ChatId, bot_msg: MessageId) -> Result<Vec<u8>> {
+    Ok(bincode::serialize(&(chat_id.0, bot_msg.0))?)
+}
+
+lazy_static! {
+    /// sled locks the database for the whole process, so it's opened once and shared
+    pub static ref DB: sled::Db = config().open().expect("Failed to open db/searches");
+}
+
+#[cfg(not(test))]
+fn config() -> sled::Config {
+    sled::Config::new()
+        .path("db/searches")
+        .cache_capacity(10_000_000)
+}
+
+/// Tests don't touch the searches of the bot
+#[cfg(test)]
+fn config() -> sled::Config {
+    sled::Config::new().temporary(true)
+}
+
+pub fn save_search(chat_id: ChatId, bot_msg: MessageId, search: &SearchData) -> Result<()> {
+    log::warn!(
+        "Save search for chat ID {}, message {}: {:#?}",
+        chat_id.0,
+        bot_msg.0,
+        search
+    );
+    DB.insert(key(chat_id, bot_msg)?, bincode::serialize(search)?)?;
+    Ok(())
+}
+
+pub fn remove_search(chat_id: ChatId, bot_msg: MessageId) -> Result<()> {
+    log::warn!(
+        "Remove search for chat ID {}, message {}",
+        chat_id.0,
+        bot_msg.0
+    );
+    DB.remove(key(chat_id, bot_msg)?)?;
+    Ok(())
+}
+
+/// Searches running when the bot stopped. `None` for the ones that can't be read anymore.
+pub fn all_searches() -> Result<Vec<(ChatId, MessageId, Option<SearchData>)>> {
+    let mut all = vec![];
+    for entry in DB.iter() {
+        let (key, value) = entry?;
+        let (chat_id, bot_msg) = bincode::deserialize::<(i64, i32)>(&key)?;
+        let search = bincode::deserialize(&value)
+            .map_err(|e| log::error!("Unreadable search of chat ID {chat_id}: {e}"))
+            .ok();
+        all.push((ChatId(chat_id), MessageId(bot_msg), search));
+    }
+    Ok(all)
+}
+
+/// Keeps the search that sent the reminder `alert_msg` for the buttons under it
+pub fn save_finished(chat_id: ChatId, alert_msg: MessageId, search: &FinishedSearch) -> Result<()> {
+    DB.open_tree("finished")?
+        .insert(key(chat_id, alert_msg)?, bincode::serialize(search)?)?;
+    Ok(())
+}
+
+pub fn take_finished(chat_id: ChatId, alert_msg: MessageId) -> Result<Option<FinishedSearch>> {
+    match DB.open_tree("finished")?.remove(key(chat_id, alert_msg)?)? {
+        Some(ivec) => Ok(Some(bincode::deserialize(&ivec)?)),
+        None => Ok(None),
+    }
+}
+
+/// Forgets the finished searches of the vehicles arrived before `timestamp`
+pub fn remove_finished_before(timestamp: i64) -> Result<()> {
+    let finished = DB.open_tree("finished")?;
+    for entry in finished.iter() {
+        let (key, value) = entry?;
+        let outdated = bincode::deserialize::<FinishedSearch>(&value)
+            .map_or(true, |search| search.arrival < timestamp);
+        if outdated {
+            finished.remove(key)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gtfs::RouteDirection;
+
+    fn search(name: &str) -> SearchData {
+        SearchData {
+            city: String::from("spb"),
+            name: name.to_string(),
+            routes: vec![
+                RouteDirection {
+                    route_id: String::from("42"),
+                    direction: String::from("1"),
+                },
+                RouteDirection {
+                    route_id: String::from("7"),
+                    direction: String::from("0"),
+                },
+            ],
+            stop_id: String::from("1001"),
+            leeway: 5,
+            started: 100,
+            not_before: None,
+            skip_trip: None,
+            skip_vehicle: None,
+        }
+    }
+
+    /// The database is shared by the tests, each one looks at its own chat only
+    fn searches_of(chat_id: ChatId) -> Vec<(MessageId, Option<SearchData>)> {
+        all_searches()
+            .unwrap()
+            .into_iter()
+            .filter(|(chat, _, _)| *chat == chat_id)
+            .map(|(_, bot_msg, search)| (bot_msg, search))
+            .collect()
+    }
+
+    #[test]
+    fn searches_saved_and_removed() {
+        let chat_id = ChatId(1);
+        save_search(chat_id, MessageId(10), &search("Работа")).unwrap();
+        save_search(chat_id, MessageId(11), &search("Дом")).unwrap();
+
+        let searches = searches_of(chat_id);
+        assert_eq!(searches.len(), 2);
+        assert_eq!(searches[0].0, MessageId(10));
+        let work = searches[0].1.as_ref().unwrap();
+        assert_eq!(work.name, "Работа");
+        assert_eq!(work.routes.len(), 2);
+        assert_eq!((work.leeway, work.started), (5, 100));
+
+        remove_search(chat_id, MessageId(10)).unwrap();
+        let searches = searches_of(chat_id);
+        assert_eq!(searches.len(), 1);
+        assert_eq!(searches[0].1.as_ref().unwrap().name, "Дом");
+
+        // Removing a search twice is fine
+        remove_search(chat_id, MessageId(10)).unwrap();
+        remove_search(chat_id, MessageId(11)).unwrap();
+        assert!(searches_of(chat_id).is_empty());
+    }
+
+    #[test]
+    fn unreadable_search_is_kept_to_be_dropped() {
+        let chat_id = ChatId(2);
+        DB.insert(key(chat_id, MessageId(20)).unwrap(), b"garbage".to_vec())
+            .unwrap();
+
+        let searches = searches_of(chat_id);
+        assert_eq!(searches.len(), 1);
+        assert!(searches[0].1.is_none());
+
+        remove_search(chat_id, MessageId(20)).unwrap();
+        assert!(searches_of(chat_id).is_empty());
+    }
+
+    fn finished(arrival: i64) -> FinishedSearch {
+        FinishedSearch {
+            search: search("Работа"),
+            arrival,
+            trip_id: Some(String::from("t1")),
+            vehicle_id: None,
+        }
+    }
+
+    #[test]
+    fn finished_searches_taken_once() {
+        let chat_id = ChatId(3);
+        // Arrives after the ones forgotten by the test below
+        save_finished(chat_id, MessageId(30), &finished(3000)).unwrap();
+
+        let taken = take_finished(chat_id, MessageId(30)).unwrap().unwrap();
+        assert_eq!(taken.arrival, 3000);
+        assert_eq!(taken.trip_id.as_deref(), Some("t1"));
+        assert_eq!(taken.search.name, "Работа");
+        // Pressing a button twice finds nothing
+        assert!(take_finished(chat_id, MessageId(30)).unwrap().is_none());
+    }
+
+    #[test]
+    fn old_finished_searches_forgotten() {
+        let chat_id = ChatId(4);
+        save_finished(chat_id, MessageId(40), &finished(1000)).unwrap();
+        save_finished(chat_id, MessageId(41), &finished(3000)).unwrap();
+
+        remove_finished_before(2000).unwrap();
+        assert!(take_finished(chat_id, MessageId(40)).unwrap().is_none());
+        assert!(take_finished(chat_id, MessageId(41)).unwrap().is_some());
+    }
+}
@@ -1,6 +1,10 @@
-use chrono::Local;
+use chrono::{Local, NaiveTime, Weekday};
 use lazy_static::lazy_static;
-use std::{collections::HashMap, error::Error, time::Duration};
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    time::{Duration, Instant},
+};
 use teloxide::{
     dispatching::{
         dialogue::{self, serializer::Json, ErasedStorage, SqliteStorage, Storage},
@@ -10,18 +14,48 @@ use teloxide::{
     types::{InlineKeyboardButton, InlineKeyboardMarkup, MenuButton, MessageId},
     utils::command::BotCommands,
 };
+use tokio::sync::broadcast::error::RecvError;
 use tokio::sync::Mutex;
 use tokio::task::JoinHandle;
 
-use crate::gtfs::{self, RouteId, StopId, Vehicle};
+use crate::alert_notifier;
+use crate::config::CONFIG;
+use crate::forecast_hub::{self, StopUpdate};
+use crate::gtfs::{
+    self, Cancellation, Coordinates, Forecast, RouteDirection, RouteId, ScheduledArrival,
+    ServiceAlert, StopId, TripId,
+};
 use crate::saved_routes_db::SavedRoutesDb;
-use crate::STATIC_FEED;
+use crate::scheduler::{self, Schedule, WEEK};
+use crate::searches_db;
+use crate::transit::{Cities, Transit};
+
+/// A search running in the background, keyed by its status message
+struct ActiveSearch {
+    data: SearchData,
+    handle: JoinHandle<HandlerResult>,
+}
 
 lazy_static! {
-    static ref POLL_TASKS: Mutex<HashMap<ChatId, JoinHandle<HandlerResult>>> =
+    static ref POLL_TASKS: Mutex<HashMap<ChatId, HashMap<MessageId, ActiveSearch>>> =
         Mutex::new(HashMap::new());
 }
 
+const NEARBY_STOPS_COUNT: usize = 6;
+const NEARBY_STOPS_RADIUS_M: f64 = 1000.0;
+const STOP_SEARCH_MIN_LEN: usize = 3;
+const STOP_SEARCH_COUNT: usize = 6;
+const VEHICLE_PINS_COUNT: usize = 3;
+const MAX_SEARCHES: usize = 5;
+const ARRIVALS_SHOWN: usize = 3;
+/// How much later "+5 мин" lets the user leave
+const SNOOZE_SEC: i64 = 5 * 60;
+const STATUS_EDIT_INTERVAL: Duration = Duration::from_secs(15);
+/// Searches older than this aren't resumed after a restart
+const SEARCH_MAX_AGE_SEC: i64 = 2 * 60 * 60;
+/// Callback data prefix of the buttons of searches, they work whatever the dialogue state is
+const SEARCH_PREFIX: &str = "search:";
+
 type MyDialogue = Dialogue<State, ErasedStorage<State>>;
 type HandlerResult = Result<(), Box<dyn std::error::Error + Send + Sync>>;
 type MyStorage = std::sync::Arc<ErasedStorage<State>>;
@@ -29,14 +63,76 @@ type MyStorage = std::sync::Arc<ErasedStorage<State>>;
 pub type SavedRouteName = String;
 #[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
 pub struct SavedRouteData {
-    route_id: RouteId,
-    stop_id: StopId,
-    direction: String,
-    leeway: u64,
+    pub city: String,
+    /// Any of them will do, whichever comes first
+    pub routes: Vec<RouteDirection>,
+    pub stop_id: StopId,
+    pub leeway: u64,
 }
 
 pub type SavedRoutes = HashMap<SavedRouteName, SavedRouteData>;
 
+/// A running search, stored to be resumed after a restart
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct SearchData {
+    pub city: String,
+    pub name: String,
+    pub routes: Vec<RouteDirection>,
+    pub stop_id: StopId,
+    pub leeway: u64,
+    /// Unix time the search was started at
+    pub started: i64,
+    /// Arrivals up to this Unix time are ignored, the user has asked for a later vehicle
+    pub not_before: Option<i64>,
+    /// Trip the user has been reminded about already
+    pub skip_trip: Option<TripId>,
+    /// Vehicle the user has been reminded about already, forecasts may have no trip
+    pub skip_vehicle: Option<String>,
+}
+
+impl SearchData {
+    /// Whether the user still waits for the arrival, not a later one
+    fn wants(&self, arrival: i64, trip_id: Option<&TripId>, vehicle_id: Option<&String>) -> bool {
+        self.not_before.is_none_or(|t| arrival > t)
+            && (self.skip_trip.is_none() || trip_id != self.skip_trip.as_ref())
+            && (self.skip_vehicle.is_none() || vehicle_id != self.skip_vehicle.as_ref())
+    }
+}
+
+/// A search that has sent its reminder, kept for the buttons under the reminder
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct FinishedSearch {
+    pub search: SearchData,
+    /// Arrival the reminder was sent for
+    pub arrival: i64,
+    pub trip_id: Option<TripId>,
+    pub vehicle_id: Option<String>,
+}
+
+impl FinishedSearch {
+    /// The search again, for a vehicle after the one of the reminder
+    fn next(self, now: i64) -> SearchData {
+        SearchData {
+            started: now,
+            not_before: Some(self.arrival),
+            skip_trip: self.trip_id,
+            skip_vehicle: self.vehicle_id,
+            ..self.search
+        }
+    }
+
+    /// The search again, for a vehicle the user can leave for in `SNOOZE_SEC` or later
+    fn snooze(self, now: i64) -> SearchData {
+        SearchData {
+            started: now,
+            not_before: Some(now + SNOOZE_SEC + self.search.leeway as i64 * 60),
+            skip_trip: None,
+            skip_vehicle: None,
+            ..self.search
+        }
+    }
+}
+
 #[derive(BotCommands, Clone)]
 #[command(
     rename_rule = "lowercase",
@@ -45,12 +141,15 @@ pub type SavedRoutes = HashMap<SavedRouteName, SavedRouteData>;
 enum Command {
     #[command(description = "Начать заново")]
     Start,
+    #[command(description = "Активные поиски")]
+    Searches,
 }
 
 #[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
 enum State {
     #[default]
     BotStart,
+    ChooseCity,
     Start {
         bot_msg: MessageId,
     },
@@ -63,6 +162,14 @@ enum State {
     RouteStop {
         route_id: RouteId,
     },
+    NearbyStop,
+    StopRoute,
+    /// Choosing several routes at the stop, any of them will do
+    StopRoutes {
+        stops: Vec<StopId>,
+        stop_id: Option<StopId>,
+        routes: Vec<RouteDirection>,
+    },
     RequestLeewayTime {
         route_id: RouteId,
         direction: String,
@@ -72,12 +179,17 @@ enum State {
         stop_id: StopId,
         direction: String,
         bot_msg: MessageId,
+        /// Other routes watched at the stop besides `route_id`
+        #[serde(default)]
+        more_routes: Vec<RouteDirection>,
     },
     SaveQuery {
         route_id: RouteId,
         stop_id: StopId,
         direction: String,
         leeway: u64,
+        #[serde(default)]
+        more_routes: Vec<RouteDirection>,
     },
     SaveQueryName {
         route_id: RouteId,
@@ -85,13 +197,36 @@ enum State {
         direction: String,
         leeway: u64,
         bot_msg: MessageId,
+        #[serde(default)]
+        more_routes: Vec<RouteDirection>,
     },
+    /// A search has been started, its buttons work whatever the state is
     Search {
         bot_msg: MessageId,
     },
+    Schedules,
+    Schedule {
+        id: u32,
+    },
+    NewScheduleRoute,
+    NewScheduleDays {
+        route: SavedRouteName,
+        days: Vec<Weekday>,
+    },
+    NewScheduleTime {
+        route: SavedRouteName,
+        days: Vec<Weekday>,
+        bot_msg: MessageId,
+    },
+    NewScheduleHolidays {
+        route: SavedRouteName,
+        days: Vec<Weekday>,
+        start: NaiveTime,
+        end: NaiveTime,
+    },
 }
 
-pub async fn bot() {
+pub async fn bot(cities: Cities) {
     let bot = Bot::from_env();
 
     let storage: MyStorage = SqliteStorage::open("db/dialogues.sqlite", Json)
@@ -99,8 +234,14 @@ pub async fn bot() {
         .unwrap()
         .erase();
 
+    for transit in cities.all() {
+        tokio::spawn(alert_notifier::run(bot.clone(), transit.clone()));
+    }
+    tokio::spawn(resume_searches(bot.clone(), cities.clone()));
+    tokio::spawn(scheduler::run(bot.clone(), cities.clone()));
+
     Dispatcher::builder(bot, schema())
-        .dependencies(dptree::deps![storage])
+        .dependencies(dptree::deps![storage, cities])
         .enable_ctrlc_handler()
         .build()
         .dispatch()
@@ -110,19 +251,26 @@ pub async fn bot() {
 fn schema() -> UpdateHandler<Box<dyn Error + Send + Sync + 'static>> {
     use dptree::case;
 
-    let command_handler =
-        teloxide::filter_command::<Command, _>().branch(case![Command::Start].endpoint(bot_start));
+    let command_handler = teloxide::filter_command::<Command, _>()
+        .branch(case![Command::Start].endpoint(bot_start))
+        .branch(case![Command::Searches].endpoint(list_searches));
 
     let message_handler = Update::filter_message()
         .branch(command_handler)
         .branch(case![State::BotStart].endpoint(bot_start))
+        .branch(
+            case![State::RouteNumber { bot_msg }]
+                .filter(|msg: Message| msg.location().is_some())
+                .endpoint(nearby_stops),
+        )
         .branch(case![State::RouteNumber { bot_msg }].endpoint(route_number))
         .branch(
             case![State::ReceiveLeewayTime {
                 route_id,
                 stop_id,
                 direction,
-                bot_msg
+                bot_msg,
+                more_routes
             }]
             .endpoint(receive_leeway_time),
         )
@@ -133,14 +281,34 @@ fn schema() -> UpdateHandler<Box<dyn Error + Send + Sync + 'static>> {
                 direction,
                 leeway,
                 bot_msg,
+                more_routes
             }]
             .endpoint(save_query_name),
         )
+        .branch(
+            case![State::NewScheduleTime {
+                route,
+                days,
+                bot_msg
+            }]
+            .endpoint(new_schedule_time),
+        )
+        .branch(case![State::ChooseCity].endpoint(delete_unexpected))
         .branch(case![State::Start { bot_msg }].endpoint(delete_unexpected))
         .branch(case![State::NewOrSaved].endpoint(delete_unexpected))
         .branch(case![State::DeleteRecord].endpoint(delete_unexpected))
         .branch(case![State::RouteDirection].endpoint(delete_unexpected))
         .branch(case![State::RouteStop { route_id }].endpoint(delete_unexpected))
+        .branch(case![State::NearbyStop].endpoint(delete_unexpected))
+        .branch(case![State::StopRoute].endpoint(delete_unexpected))
+        .branch(
+            case![State::StopRoutes {
+                stops,
+                stop_id,
+                routes
+            }]
+            .endpoint(delete_unexpected),
+        )
         .branch(
             case![State::RequestLeewayTime {
                 route_id,
@@ -153,18 +321,44 @@ fn schema() -> UpdateHandler<Box<dyn Error + Send + Sync + 'static>> {
                 route_id,
                 stop_id,
                 direction,
-                leeway
+                leeway,
+                more_routes
             }]
             .endpoint(delete_unexpected),
         )
-        .branch(case![State::Search { bot_msg }].endpoint(delete_unexpected));
+        .branch(case![State::Search { bot_msg }].endpoint(delete_unexpected))
+        .branch(case![State::Schedules].endpoint(delete_unexpected))
+        .branch(case![State::Schedule { id }].endpoint(delete_unexpected))
+        .branch(case![State::NewScheduleRoute].endpoint(delete_unexpected))
+        .branch(case![State::NewScheduleDays { route, days }].endpoint(delete_unexpected))
+        .branch(
+            case![State::NewScheduleHolidays {
+                route,
+                days,
+                start,
+                end
+            }]
+            .endpoint(delete_unexpected),
+        );
 
     let callback_query_handler = Update::filter_callback_query()
+        .branch(dptree::filter(is_search_button).endpoint(search))
+        .branch(case![State::ChooseCity].endpoint(choose_city))
         .branch(case![State::Start { bot_msg }].endpoint(start))
         .branch(case![State::NewOrSaved].endpoint(new_or_saved))
         .branch(case![State::DeleteRecord].endpoint(delete_record))
         .branch(case![State::RouteDirection].endpoint(route_direction))
         .branch(case![State::RouteStop { route_id }].endpoint(route_stop))
+        .branch(case![State::NearbyStop].endpoint(nearby_stop))
+        .branch(case![State::StopRoute].endpoint(stop_route))
+        .branch(
+            case![State::StopRoutes {
+                stops,
+                stop_id,
+                routes
+            }]
+            .endpoint(stop_routes),
+        )
         .branch(
             case![State::RequestLeewayTime {
                 route_id,
@@ -177,26 +371,103 @@ fn schema() -> UpdateHandler<Box<dyn Error + Send + Sync + 'static>> {
                 route_id,
                 stop_id,
                 direction,
-                leeway
+                leeway,
+                more_routes
             }]
             .endpoint(save_query),
         )
-        .branch(case![State::Search { bot_msg }].endpoint(search));
+        .branch(case![State::Search { bot_msg }].endpoint(old_search_button))
+        .branch(case![State::Schedules].endpoint(schedules))
+        .branch(case![State::Schedule { id }].endpoint(schedule))
+        .branch(case![State::NewScheduleRoute].endpoint(new_schedule_route))
+        .branch(case![State::NewScheduleDays { route, days }].endpoint(new_schedule_days))
+        .branch(
+            case![State::NewScheduleHolidays {
+                route,
+                days,
+                start,
+                end
+            }]
+            .endpoint(new_schedule_holidays),
+        );
 
     dialogue::enter::<Update, ErasedStorage<State>, State, _>()
+        .map_async(chat_transit)
         .branch(message_handler)
         .branch(callback_query_handler)
 }
 
-async fn bot_start(bot: Bot, dialogue: MyDialogue) -> HandlerResult {
+/// Provider of the city the chat has chosen
+async fn chat_transit(dialogue: MyDialogue, cities: Cities) -> Transit {
+    let city = dialogue.chat_id().get_city().unwrap_or_else(|e| {
+        log::error!("Failed to get city of chat ID {}: {e}", dialogue.chat_id());
+        None
+    });
+    cities.get(city.as_deref())
+}
+
+async fn bot_start(bot: Bot, dialogue: MyDialogue, cities: Cities) -> HandlerResult {
     bot.set_my_commands(Command::bot_commands()).await?;
     bot.set_chat_menu_button()
         .menu_button(MenuButton::Commands)
         .chat_id(dialogue.chat_id())
         .await?;
 
+    if let Some(searches) = POLL_TASKS.lock().await.remove(&dialogue.chat_id()) {
+        for (bot_msg, search) in searches {
+            search.handle.abort();
+            if let Err(e) = searches_db::remove_search(dialogue.chat_id(), bot_msg) {
+                log::error!(
+                    "Failed to remove search of chat ID {}: {e}",
+                    dialogue.chat_id()
+                );
+            }
+        }
+    }
+
     delete_all(bot.clone(), dialogue.clone()).await;
 
+    if cities.all().len() > 1 {
+        let keys: Vec<Vec<InlineKeyboardButton>> = cities
+            .all()
+            .iter()
+            .map(|city| vec![InlineKeyboardButton::callback(city.name(), city.id())])
+            .collect();
+
+        bot.send_message(dialogue.chat_id(), "🏙Выберите город:")
+            .reply_markup(InlineKeyboardMarkup::new(keys))
+            .await?;
+
+        dialogue.update(State::ChooseCity).await?;
+        return Ok(());
+    }
+
+    dialogue.chat_id().set_city(cities.get(None).id())?;
+    show_start_button(bot, dialogue).await
+}
+
+async fn choose_city(
+    bot: Bot,
+    dialogue: MyDialogue,
+    cities: Cities,
+    q: CallbackQuery,
+) -> HandlerResult {
+    log::warn!("ChooseCity:\r\n{q:#?}");
+
+    bot.answer_callback_query(q.id).await?;
+
+    if let Some(city) = q.data {
+        dialogue.chat_id().set_city(cities.get(Some(&city)).id())?;
+        if let Some(msg) = q.message {
+            bot.delete_message(dialogue.chat_id(), msg.id).await?;
+        }
+        show_start_button(bot, dialogue).await?;
+    }
+
+    Ok(())
+}
+
+async fn show_start_button(bot: Bot, dialogue: MyDialogue) -> HandlerResult {
     let keys: Vec<Vec<InlineKeyboardButton>> = vec![vec![InlineKeyboardButton::callback(
         "Начать работу",
         "start",
@@ -214,6 +485,7 @@ async fn bot_start(bot: Bot, dialogue: MyDialogue) -> HandlerResult {
 async fn start(
     bot: Bot,
     dialogue: MyDialogue,
+    transit: Transit,
     bot_msg: MessageId,
     q: CallbackQuery,
 ) -> HandlerResult {
@@ -221,34 +493,46 @@ async fn start(
 
     bot.answer_callback_query(q.id).await?;
 
+    bot.edit_message_text(dialogue.chat_id(), bot_msg, "🚗Куда едем?🚙")
+        .reply_markup(main_menu(&dialogue, &transit)?)
+        .await?;
+
+    dialogue.update(State::NewOrSaved).await?;
+    Ok(())
+}
+
+/// A new route or one of the saved ones
+fn main_menu(dialogue: &MyDialogue, transit: &Transit) -> anyhow::Result<InlineKeyboardMarkup> {
     let mut keys: Vec<Vec<InlineKeyboardButton>> = vec![vec![InlineKeyboardButton::callback(
         "Новый маршрут",
         "new_route",
     )]];
 
-    let saved_routes = dialogue.chat_id().get_saved_routes()?;
+    let saved_routes = city_saved_routes(dialogue, transit)?;
 
     for key in saved_routes.keys() {
         keys.push(vec![InlineKeyboardButton::callback(key, key)]);
     }
 
     if !saved_routes.is_empty() {
+        keys.push(vec![InlineKeyboardButton::callback(
+            "⏰Расписания",
+            "schedules",
+        )]);
         keys.push(vec![InlineKeyboardButton::callback(
             "Удалить сохраненный",
             "delete",
         )]);
     }
-    let keyboard = InlineKeyboardMarkup::new(keys);
-
-    bot.edit_message_text(dialogue.chat_id(), bot_msg, "🚗Куда едем?🚙")
-        .reply_markup(keyboard)
-        .await?;
-
-    dialogue.update(State::NewOrSaved).await?;
-    Ok(())
+    Ok(InlineKeyboardMarkup::new(keys))
 }
 
-async fn new_or_saved(bot: Bot, dialogue: MyDialogue, q: CallbackQuery) -> HandlerResult {
+async fn new_or_saved(
+    bot: Bot,
+    dialogue: MyDialogue,
+    transit: Transit,
+    q: CallbackQuery,
+) -> HandlerResult {
     log::warn!("NewOrSaved:\r\n{q:#?}");
 
     bot.answer_callback_query(q.id).await?;
@@ -256,7 +540,7 @@ async fn new_or_saved(bot: Bot, dialogue: MyDialogue, q: CallbackQuery) -> Handl
     let bot_msg = q.message.unwrap().id;
 
     if let Some(select) = q.data {
-        let saved_routes = dialogue.chat_id().get_saved_routes()?;
+        let saved_routes = city_saved_routes(&dialogue, &transit)?;
         if select == "delete" {
             let mut keys: Vec<Vec<InlineKeyboardButton>> = vec![];
             for name in saved_routes.keys() {
@@ -269,47 +553,27 @@ async fn new_or_saved(bot: Bot, dialogue: MyDialogue, q: CallbackQuery) -> Handl
                 .await?;
 
             dialogue.update(State::DeleteRecord).await?;
+        } else if select == "schedules" {
+            show_schedules(&bot, &dialogue, bot_msg).await?;
         } else if let Some(route_data) = saved_routes.get(&select) {
-            let keys: Vec<Vec<InlineKeyboardButton>> = vec![vec![InlineKeyboardButton::callback(
-                "🚫Отменить поиск🚫",
-                String::from("cancel"),
-            )]];
-            let keyboard = InlineKeyboardMarkup::new(keys);
-
-            bot.edit_message_text(
-                dialogue.chat_id(),
-                bot_msg,
-                "✅Готово! Я пришлю напоминание перед выходом",
-            )
-            .reply_markup(keyboard)
-            .await?;
-
-            let polling_handle = tokio::spawn(look_for_transport(
+            start_search(
                 bot,
-                dialogue.clone(),
+                dialogue,
+                transit,
+                bot_msg,
+                select.clone(),
                 (
-                    route_data.route_id.clone(),
+                    route_data.routes.clone(),
                     route_data.stop_id.clone(),
-                    route_data.direction.clone(),
-                    route_data.leeway as i64,
-                    bot_msg,
+                    route_data.leeway,
                 ),
-            ));
-
-            if let Some(task) = POLL_TASKS
-                .lock()
-                .await
-                .insert(dialogue.chat_id(), polling_handle)
-            {
-                task.abort();
-            }
-
-            dialogue.update(State::Search { bot_msg }).await?;
+            )
+            .await?;
         } else {
             bot.edit_message_text(
                 dialogue.chat_id(),
                 bot_msg,
-                "🔢Введите номер маршрута, например 1Кр🔢",
+                "🔢Введите номер маршрута, например 1Кр, или название остановки🔢\r\nМожно отправить геопозицию📍",
             )
             .await?;
 
@@ -320,6 +584,13 @@ async fn new_or_saved(bot: Bot, dialogue: MyDialogue, q: CallbackQuery) -> Handl
     Ok(())
 }
 
+/// Saved routes of the chat in its current city
+fn city_saved_routes(dialogue: &MyDialogue, transit: &Transit) -> anyhow::Result<SavedRoutes> {
+    let mut routes = dialogue.chat_id().get_saved_routes()?;
+    routes.retain(|_, data| data.city == transit.id());
+    Ok(routes)
+}
+
 async fn delete_record(bot: Bot, dialogue: MyDialogue, q: CallbackQuery) -> HandlerResult {
     log::warn!("DeleteRecord:\r\n{q:#?}");
 
@@ -345,174 +616,833 @@ async fn delete_record(bot: Bot, dialogue: MyDialogue, q: CallbackQuery) -> Hand
     Ok(())
 }
 
-async fn route_number(
+/// Schedules of the chat, a button for each to pause or delete it
+async fn show_schedules(bot: &Bot, dialogue: &MyDialogue, bot_msg: MessageId) -> HandlerResult {
+    let schedules = dialogue.chat_id().get_schedules()?;
+
+    let mut keys: Vec<Vec<InlineKeyboardButton>> = schedules
+        .iter()
+        .map(|schedule| {
+            vec![InlineKeyboardButton::callback(
+                schedule.describe(),
+                schedule.id.to_string(),
+            )]
+        })
+        .collect();
+    keys.push(vec![InlineKeyboardButton::callback(
+        "➕Новое расписание",
+        "new",
+    )]);
+    keys.push(vec![InlineKeyboardButton::callback("⬅️Назад", "back")]);
+    let keyboard = InlineKeyboardMarkup::new(keys);
+
+    let text = if schedules.is_empty() {
+        "⏰Расписаний пока нет. Я могу сам начинать поиск сохраненного маршрута в нужные дни и время"
+    } else {
+        "⏰Расписания, нажмите, чтобы приостановить или удалить:"
+    };
+    bot.edit_message_text(dialogue.chat_id(), bot_msg, text)
+        .reply_markup(keyboard)
+        .await?;
+
+    dialogue.update(State::Schedules).await?;
+    Ok(())
+}
+
+async fn schedules(
     bot: Bot,
     dialogue: MyDialogue,
-    bot_msg: MessageId,
-    msg: Message,
+    transit: Transit,
+    q: CallbackQuery,
 ) -> HandlerResult {
-    log::warn!("RouteNumber:\r\n{msg:#?}");
+    log::warn!("Schedules:\r\n{q:#?}");
 
-    if let Some(number) = msg.text() {
-        let mut keys: Vec<Vec<InlineKeyboardButton>> = vec![];
-        {
-            let feed = STATIC_FEED.read().await;
-            let number = number.to_uppercase();
+    bot.answer_callback_query(q.id).await?;
 
-            if let Some(bus) = feed.routes.bus.get(&number) {
-                keys.push(vec![InlineKeyboardButton::callback(
-                    format!("{} {}", Vehicle::Bus, number),
-                    bus.clone().id,
-                )]);
-            }
-            if let Some(trolley) = feed.routes.trolley.get(&number) {
-                keys.push(vec![InlineKeyboardButton::callback(
-                    format!("{} {}", Vehicle::Trolley, number),
-                    trolley.clone().id,
-                )]);
-            }
-            if let Some(tram) = feed.routes.tram.get(&number) {
-                keys.push(vec![InlineKeyboardButton::callback(
-                    format!("{} {}", Vehicle::Tram, number),
-                    tram.clone().id,
-                )]);
-            }
-        }
+    let bot_msg = q.message.unwrap().id;
 
-        if !keys.is_empty() {
+    if let Some(select) = q.data {
+        if select == "back" {
+            bot.edit_message_text(dialogue.chat_id(), bot_msg, "🚗Куда едем?🚙")
+                .reply_markup(main_menu(&dialogue, &transit)?)
+                .await?;
+
+            dialogue.update(State::NewOrSaved).await?;
+        } else if select == "new" {
+            let mut keys: Vec<Vec<InlineKeyboardButton>> = city_saved_routes(&dialogue, &transit)?
+                .keys()
+                .map(|name| vec![InlineKeyboardButton::callback(name, name)])
+                .collect();
+            keys.push(vec![InlineKeyboardButton::callback("⬅️Назад", "back")]);
             let keyboard = InlineKeyboardMarkup::new(keys);
 
-            bot.edit_message_text(dialogue.chat_id(), bot_msg, "🔍 Вот что удалось найти:")
-                .reply_markup(keyboard)
-                .await?;
-            dialogue.update(State::RouteDirection).await?;
-        } else {
             bot.edit_message_text(
                 dialogue.chat_id(),
                 bot_msg,
-                "🤖 К сожалению, я ничего не нашел. Попробуйте ввести другой номер.",
+                "🔖Какой маршрут искать по расписанию?",
             )
+            .reply_markup(keyboard)
             .await?;
-            dialogue.update(State::RouteNumber { bot_msg }).await?;
+
+            dialogue.update(State::NewScheduleRoute).await?;
+        } else if let Ok(id) = select.parse::<u32>() {
+            let schedules = dialogue.chat_id().get_schedules()?;
+            let Some(schedule) = schedules.iter().find(|schedule| schedule.id == id) else {
+                return show_schedules(&bot, &dialogue, bot_msg).await;
+            };
+
+            let pause = if schedule.paused {
+                "▶️Возобновить"
+            } else {
+                "⏸Приостановить"
+            };
+            let keys: Vec<Vec<InlineKeyboardButton>> = vec![
+                vec![InlineKeyboardButton::callback(pause, "pause")],
+                vec![InlineKeyboardButton::callback("🗑Удалить", "delete")],
+                vec![InlineKeyboardButton::callback("⬅️Назад", "back")],
+            ];
+            let keyboard = InlineKeyboardMarkup::new(keys);
+
+            bot.edit_message_text(dialogue.chat_id(), bot_msg, schedule.describe())
+                .reply_markup(keyboard)
+                .await?;
+
+            dialogue.update(State::Schedule { id }).await?;
         }
     }
-    bot.delete_message(dialogue.chat_id(), msg.id).await?;
     Ok(())
 }
 
-async fn route_direction(bot: Bot, dialogue: MyDialogue, q: CallbackQuery) -> HandlerResult {
-    log::warn!("RouteDirection:\r\n{q:#?}");
+async fn schedule(bot: Bot, dialogue: MyDialogue, id: u32, q: CallbackQuery) -> HandlerResult {
+    log::warn!("Schedule:\r\n{q:#?}");
 
-    bot.answer_callback_query(q.id.clone()).await?;
+    bot.answer_callback_query(q.id).await?;
 
     let bot_msg = q.message.unwrap().id;
 
-    if let Some(route_id) = q.data {
-        let route_name = gtfs::route_name(&route_id).await?;
-
-        let keys: Vec<Vec<InlineKeyboardButton>> = vec![vec![
-            InlineKeyboardButton::callback("➡️Туда➡️", String::from("0")),
-            InlineKeyboardButton::callback("⬅️Обратно⬅️", String::from("1")),
-        ]];
-        let keyboard = InlineKeyboardMarkup::new(keys);
-
-        bot.edit_message_text(
-            dialogue.chat_id(),
-            bot_msg,
-            format!("{route_name}\r\nВыберите направление:"),
-        )
-        .reply_markup(keyboard)
-        .await?;
-
-        dialogue.update(State::RouteStop { route_id }).await?;
+    if let Some(action) = q.data {
+        let mut chat_id = dialogue.chat_id();
+        let mut schedules = chat_id.get_schedules()?;
+        if action == "pause" {
+            if let Some(schedule) = schedules.iter_mut().find(|schedule| schedule.id == id) {
+                schedule.paused = !schedule.paused;
+            }
+            chat_id.set_schedules(&schedules)?;
+        } else if action == "delete" {
+            schedules.retain(|schedule| schedule.id != id);
+            chat_id.set_schedules(&schedules)?;
+        }
+        show_schedules(&bot, &dialogue, bot_msg).await?;
     }
     Ok(())
 }
 
-async fn route_stop(
+async fn new_schedule_route(
     bot: Bot,
     dialogue: MyDialogue,
-    route_id: RouteId,
+    transit: Transit,
     q: CallbackQuery,
 ) -> HandlerResult {
-    log::warn!("RouteStop:\r\n{q:#?}");
+    log::warn!("NewScheduleRoute:\r\n{q:#?}");
 
     bot.answer_callback_query(q.id).await?;
 
-    let msg = q.message.unwrap().id;
-
-    if let Some(mut direction) = q.data {
-        let mut stops = gtfs::stops_on_route(&route_id, &direction).await;
-        // There are circular routes that serve only in one direction (Bus 261 for example).
-        // Nevertheless, they have trip IDs for return route (that doesn't exist) and that trip IDs are not presented in `stop_times.txt`
-        // that means we will always fail when trying to get corresponding stops. So this weird workaround designed to handle this shit correctly.
-        if stops.is_err() {
-            direction = if direction == "0" {
-                "1".to_string()
-            } else {
-                "0".to_string()
-            };
-            stops = gtfs::stops_on_route(&route_id, &direction).await;
-        }
-
-        let mut keys: Vec<Vec<InlineKeyboardButton>> = vec![];
+    let bot_msg = q.message.unwrap().id;
 
-        for id in stops.unwrap() {
-            let name = gtfs::stop_name(&id).await?;
-            keys.push(vec![InlineKeyboardButton::callback(name, id)]);
+    if let Some(route) = q.data {
+        if !city_saved_routes(&dialogue, &transit)?.contains_key(&route) {
+            return show_schedules(&bot, &dialogue, bot_msg).await;
         }
-        let keyboard = InlineKeyboardMarkup::new(keys);
 
-        bot.edit_message_text(dialogue.chat_id(), msg, "🚏Выберите остановку:")
-            .reply_markup(keyboard)
+        bot.edit_message_text(dialogue.chat_id(), bot_msg, "📅По каким дням?")
+            .reply_markup(days_keyboard(&[]))
             .await?;
 
         dialogue
-            .update(State::RequestLeewayTime {
-                route_id,
-                direction,
+            .update(State::NewScheduleDays {
+                route,
+                days: vec![],
             })
             .await?;
     }
     Ok(())
 }
 
-async fn request_leeway_time(
+/// Days of the week to toggle, chosen ones are marked
+fn days_keyboard(days: &[Weekday]) -> InlineKeyboardMarkup {
+    let week = WEEK
+        .iter()
+        .map(|day| {
+            let name = scheduler::day_name(*day);
+            let text = if days.contains(day) {
+                format!("✅{name}")
+            } else {
+                name.to_string()
+            };
+            InlineKeyboardButton::callback(text, day.num_days_from_monday().to_string())
+        })
+        .collect();
+    let keys: Vec<Vec<InlineKeyboardButton>> = vec![
+        week,
+        vec![
+            InlineKeyboardButton::callback("По будням", "weekdays"),
+            InlineKeyboardButton::callback("Каждый день", "all"),
+        ],
+        vec![InlineKeyboardButton::callback("Готово", "done")],
+    ];
+    InlineKeyboardMarkup::new(keys)
+}
+
+async fn new_schedule_days(
     bot: Bot,
     dialogue: MyDialogue,
-    (route_id, direction): (RouteId, String),
+    (route, mut days): (SavedRouteName, Vec<Weekday>),
     q: CallbackQuery,
 ) -> HandlerResult {
-    log::warn!("RequestLeewayTime:\r\n{q:#?}");
+    log::warn!("NewScheduleDays:\r\n{q:#?}");
 
     bot.answer_callback_query(q.id).await?;
 
-    if let Some(stop_id) = q.data {
-        let bot_msg = q.message.unwrap().id;
-
-        bot.edit_message_text(
-            dialogue.chat_id(),
-            bot_msg,
-            "🕗Сколько минут идти до остановки?",
-        )
-        .await?;
+    let bot_msg = q.message.unwrap().id;
 
-        dialogue
-            .update(State::ReceiveLeewayTime {
-                route_id,
-                stop_id,
-                direction,
+    if let Some(select) = q.data {
+        if select == "done" {
+            if days.is_empty() {
+                return Ok(());
+            }
+            bot.edit_message_text(
+                dialogue.chat_id(),
                 bot_msg,
-            })
+                "🕗Когда искать? Введите время начала и конца, например 08:10-08:40",
+            )
             .await?;
-    }
-    Ok(())
-}
 
-async fn receive_leeway_time(
-    bot: Bot,
+            dialogue
+                .update(State::NewScheduleTime {
+                    route,
+                    days,
+                    bot_msg,
+                })
+                .await?;
+            return Ok(());
+        }
+
+        if select == "weekdays" {
+            days = WEEK[..5].to_vec();
+        } else if select == "all" {
+            days = WEEK.to_vec();
+        } else if let Some(day) = select.parse::<usize>().ok().and_then(|day| WEEK.get(day)) {
+            if days.contains(day) {
+                days.retain(|chosen| chosen != day);
+            } else {
+                days.push(*day);
+            }
+        }
+
+        bot.edit_message_reply_markup(dialogue.chat_id(), bot_msg)
+            .reply_markup(days_keyboard(&days))
+            .await?;
+
+        dialogue
+            .update(State::NewScheduleDays { route, days })
+            .await?;
+    }
+    Ok(())
+}
+
+/// Start and end of a window like "08:10-08:40", or "23:30-00:30" running past midnight
+fn parse_time_window(text: &str) -> Option<(NaiveTime, NaiveTime)> {
+    let (start, end) = text.split_once(['-', '–'])?;
+    let start = NaiveTime::parse_from_str(start.trim(), "%H:%M").ok()?;
+    let end = NaiveTime::parse_from_str(end.trim(), "%H:%M").ok()?;
+    (start != end).then_some((start, end))
+}
+
+async fn new_schedule_time(
+    bot: Bot,
+    dialogue: MyDialogue,
+    (route, days, bot_msg): (SavedRouteName, Vec<Weekday>, MessageId),
+    msg: Message,
+) -> HandlerResult {
+    log::warn!("NewScheduleTime:\r\n{msg:#?}");
+
+    bot.delete_message(dialogue.chat_id(), msg.id).await?;
+
+    let Some((start, end)) = msg.text().and_then(parse_time_window) else {
+        bot.edit_message_text(
+            dialogue.chat_id(),
+            bot_msg,
+            "🤷Не понял время. Введите разные начало и конец, например 08:10-08:40",
+        )
+        .await?;
+        return Ok(());
+    };
+
+    let keys: Vec<Vec<InlineKeyboardButton>> = vec![vec![
+        InlineKeyboardButton::callback("Да", "yes"),
+        InlineKeyboardButton::callback("Нет", "no"),
+    ]];
+    let keyboard = InlineKeyboardMarkup::new(keys);
+
+    bot.edit_message_text(dialogue.chat_id(), bot_msg, "🎉Пропускать праздники?")
+        .reply_markup(keyboard)
+        .await?;
+
+    dialogue
+        .update(State::NewScheduleHolidays {
+            route,
+            days,
+            start,
+            end,
+        })
+        .await?;
+    Ok(())
+}
+
+async fn new_schedule_holidays(
+    bot: Bot,
+    dialogue: MyDialogue,
+    (route, days, start, end): (SavedRouteName, Vec<Weekday>, NaiveTime, NaiveTime),
+    q: CallbackQuery,
+) -> HandlerResult {
+    log::warn!("NewScheduleHolidays:\r\n{q:#?}");
+
+    bot.answer_callback_query(q.id).await?;
+
+    let bot_msg = q.message.unwrap().id;
+
+    if let Some(skip) = q.data {
+        let mut chat_id = dialogue.chat_id();
+        let mut schedules = chat_id.get_schedules()?;
+        let id = schedules
+            .iter()
+            .map(|schedule| schedule.id + 1)
+            .max()
+            .unwrap_or_default();
+        schedules.push(Schedule {
+            id,
+            route,
+            days,
+            start,
+            end,
+            skip_holidays: skip == "yes",
+            paused: false,
+            last_run: None,
+            search: None,
+        });
+        chat_id.set_schedules(&schedules)?;
+
+        show_schedules(&bot, &dialogue, bot_msg).await?;
+    }
+    Ok(())
+}
+
+async fn route_number(
+    bot: Bot,
+    dialogue: MyDialogue,
+    transit: Transit,
+    bot_msg: MessageId,
+    msg: Message,
+) -> HandlerResult {
+    log::warn!("RouteNumber:\r\n{msg:#?}");
+
+    if let Some(number) = msg.text() {
+        let mut keys: Vec<Vec<InlineKeyboardButton>> = vec![];
+        {
+            let number = number.to_uppercase();
+
+            for (vehicle, route_id) in transit.routes_by_number(&number).await {
+                keys.push(vec![InlineKeyboardButton::callback(
+                    format!("{} {}", vehicle, number),
+                    route_id,
+                )]);
+            }
+        }
+
+        // Route numbers are short, so look for stops only when there's something to compare
+        if number.chars().count() >= STOP_SEARCH_MIN_LEN {
+            for stop_id in transit.search_stops(number, STOP_SEARCH_COUNT).await {
+                let name = transit.stop_name(&stop_id).await?;
+                let numbers = route_numbers_at_stop(&transit, &stop_id).await;
+                keys.push(vec![InlineKeyboardButton::callback(
+                    format!("🚏{name}: {numbers}"),
+                    format!("stop:{stop_id}"),
+                )]);
+            }
+        }
+
+        if !keys.is_empty() {
+            let keyboard = InlineKeyboardMarkup::new(keys);
+
+            bot.edit_message_text(dialogue.chat_id(), bot_msg, "🔍 Вот что удалось найти:")
+                .reply_markup(keyboard)
+                .await?;
+            dialogue.update(State::RouteDirection).await?;
+        } else {
+            bot.edit_message_text(
+                dialogue.chat_id(),
+                bot_msg,
+                "🤖 К сожалению, я ничего не нашел. Попробуйте ввести другой номер или название.",
+            )
+            .await?;
+            dialogue.update(State::RouteNumber { bot_msg }).await?;
+        }
+    }
+    bot.delete_message(dialogue.chat_id(), msg.id).await?;
+    Ok(())
+}
+
+async fn nearby_stops(
+    bot: Bot,
+    dialogue: MyDialogue,
+    transit: Transit,
+    bot_msg: MessageId,
+    msg: Message,
+) -> HandlerResult {
+    log::warn!("NearbyStops:\r\n{msg:#?}");
+
+    if let Some(location) = msg.location() {
+        let point = Coordinates {
+            lat: location.latitude,
+            lon: location.longitude,
+        };
+        let stops = transit
+            .nearest_stops(point, NEARBY_STOPS_COUNT, NEARBY_STOPS_RADIUS_M)
+            .await;
+
+        let mut keys: Vec<Vec<InlineKeyboardButton>> = vec![];
+        for (stop_id, distance) in stops {
+            let name = transit.stop_name(&stop_id).await?;
+            let numbers = route_numbers_at_stop(&transit, &stop_id).await;
+            keys.push(vec![InlineKeyboardButton::callback(
+                format!("🚏{name}, {distance:.0} м: {numbers}"),
+                stop_id,
+            )]);
+        }
+
+        if !keys.is_empty() {
+            let keyboard = InlineKeyboardMarkup::new(keys);
+
+            bot.edit_message_text(dialogue.chat_id(), bot_msg, "🚏Ближайшие остановки:")
+                .reply_markup(keyboard)
+                .await?;
+            dialogue.update(State::NearbyStop).await?;
+        } else {
+            bot.edit_message_text(
+                dialogue.chat_id(),
+                bot_msg,
+                "🤖 К сожалению, рядом нет остановок. Попробуйте ввести номер маршрута.",
+            )
+            .await?;
+            dialogue.update(State::RouteNumber { bot_msg }).await?;
+        }
+    }
+    bot.delete_message(dialogue.chat_id(), msg.id).await?;
+    Ok(())
+}
+
+async fn nearby_stop(
+    bot: Bot,
+    dialogue: MyDialogue,
+    transit: Transit,
+    q: CallbackQuery,
+) -> HandlerResult {
+    log::warn!("NearbyStop:\r\n{q:#?}");
+
+    bot.answer_callback_query(q.id).await?;
+
+    let bot_msg = q.message.unwrap().id;
+
+    if let Some(stop_id) = q.data {
+        show_stop_routes(bot, dialogue, &transit, bot_msg, &[stop_id]).await?;
+    }
+    Ok(())
+}
+
+async fn show_stop_routes(
+    bot: Bot,
+    dialogue: MyDialogue,
+    transit: &Transit,
+    bot_msg: MessageId,
+    stops: &[StopId],
+) -> HandlerResult {
+    let Some(stop_id) = stops.first() else {
+        return Ok(());
+    };
+    let stop_name = transit.stop_name(stop_id).await?;
+    let keyboard = stop_routes_keyboard(transit, stops, None)
+        .await
+        .append_row(vec![InlineKeyboardButton::callback(
+            "🔀Любой из нескольких маршрутов",
+            format!("multi:{stop_id}"),
+        )]);
+
+    bot.edit_message_text(
+        dialogue.chat_id(),
+        bot_msg,
+        format!("{stop_name}\r\nВыберите маршрут:"),
+    )
+    .reply_markup(keyboard)
+    .await?;
+
+    dialogue.update(State::StopRoute).await?;
+    Ok(())
+}
+
+/// Comma separated numbers of the routes serving the stop
+async fn route_numbers_at_stop(transit: &Transit, stop_id: &StopId) -> String {
+    let mut numbers = vec![];
+    for route in transit.routes_at_stop(stop_id).await {
+        if let Ok(number) = transit.route_number(&route.route_id).await {
+            if !numbers.contains(&number) {
+                numbers.push(number);
+            }
+        }
+    }
+    numbers.join(", ")
+}
+
+/// Route and direction buttons for every route serving the stops, `chosen` routes at the stop
+/// are marked
+async fn stop_routes_keyboard(
+    transit: &Transit,
+    stops: &[StopId],
+    chosen: Option<(&StopId, &[RouteDirection])>,
+) -> InlineKeyboardMarkup {
+    let mut buttons = vec![];
+    for stop_id in stops {
+        for route in transit.routes_at_stop(stop_id).await {
+            // One broken route must not hide the others
+            let (text, data) = match route_button(transit, &route, stop_id).await {
+                Ok(Some(button)) => button,
+                Ok(None) => continue,
+                Err(e) => {
+                    log::error!(
+                        "Route {} left out of the keyboard of stop {stop_id}: {e}",
+                        route.route_id
+                    );
+                    continue;
+                }
+            };
+            let mark = match chosen {
+                Some((chosen_stop, routes))
+                    if chosen_stop == stop_id && routes.contains(&route) =>
+                {
+                    "✅"
+                }
+                _ => "",
+            };
+            buttons.push((text, mark, data));
+        }
+    }
+    buttons.sort();
+
+    let keys: Vec<Vec<InlineKeyboardButton>> = buttons
+        .into_iter()
+        .map(|(text, mark, data)| {
+            vec![InlineKeyboardButton::callback(
+                mark.to_string() + &text,
+                data,
+            )]
+        })
+        .collect();
+    InlineKeyboardMarkup::new(keys)
+}
+
+/// Text and data of the button of a route at the stop, `None` if the route has no stops
+async fn route_button(
+    transit: &Transit,
+    route: &gtfs::RouteDirection,
+    stop_id: &StopId,
+) -> anyhow::Result<Option<(String, String)>> {
+    let title = transit.route_title(&route.route_id).await?;
+    let stops = transit
+        .stops_on_route(&route.route_id, &route.direction)
+        .await?;
+    let Some(terminal) = stops.last() else {
+        return Ok(None);
+    };
+    let terminal = transit.stop_name(terminal).await?;
+    Ok(Some((
+        format!("{title} ➡️ {terminal}"),
+        format!("{}:{}:{stop_id}", route.route_id, route.direction),
+    )))
+}
+
+/// Route, direction and stop of a stop route button
+fn parse_stop_route(data: &str) -> Option<(RouteDirection, StopId)> {
+    let mut parts = data.splitn(3, ':');
+    let route = RouteDirection {
+        route_id: parts.next()?.to_string(),
+        direction: parts.next()?.to_string(),
+    };
+    Some((route, parts.next()?.to_string()))
+}
+
+async fn stop_route(
+    bot: Bot,
+    dialogue: MyDialogue,
+    transit: Transit,
+    q: CallbackQuery,
+) -> HandlerResult {
+    log::warn!("StopRoute:\r\n{q:#?}");
+
+    bot.answer_callback_query(q.id).await?;
+
+    let data = q.data.unwrap_or_default();
+    let bot_msg = q.message.unwrap().id;
+
+    if let Some(stop_id) = data.strip_prefix("multi:") {
+        let stops = transit.stops_with_same_name(&stop_id.to_string()).await;
+        return show_stop_routes_choice(bot, dialogue, &transit, bot_msg, stops, None, vec![])
+            .await;
+    }
+
+    if let Some((route, stop_id)) = parse_stop_route(&data) {
+        bot.edit_message_text(
+            dialogue.chat_id(),
+            bot_msg,
+            "🕗Сколько минут идти до остановки?",
+        )
+        .await?;
+
+        dialogue
+            .update(State::ReceiveLeewayTime {
+                route_id: route.route_id,
+                stop_id,
+                direction: route.direction,
+                bot_msg,
+                more_routes: vec![],
+            })
+            .await?;
+    }
+    Ok(())
+}
+
+/// Routes at the stops to tick, for a search of whichever comes first
+async fn show_stop_routes_choice(
+    bot: Bot,
+    dialogue: MyDialogue,
+    transit: &Transit,
+    bot_msg: MessageId,
+    stops: Vec<StopId>,
+    stop_id: Option<StopId>,
+    routes: Vec<RouteDirection>,
+) -> HandlerResult {
+    let Some(first) = stops.first() else {
+        return Ok(());
+    };
+    let stop_name = transit.stop_name(first).await?;
+    let chosen = stop_id.as_ref().map(|stop_id| (stop_id, routes.as_slice()));
+    let keyboard = stop_routes_keyboard(transit, &stops, chosen)
+        .await
+        .append_row(vec![InlineKeyboardButton::callback("Готово", "done")]);
+
+    bot.edit_message_text(
+        dialogue.chat_id(),
+        bot_msg,
+        format!(
+            "{stop_name}\r\nОтметьте маршруты одной остановки, я напомню о том, что придет раньше:"
+        ),
+    )
+    .reply_markup(keyboard)
+    .await?;
+
+    dialogue
+        .update(State::StopRoutes {
+            stops,
+            stop_id,
+            routes,
+        })
+        .await?;
+    Ok(())
+}
+
+async fn stop_routes(
+    bot: Bot,
+    dialogue: MyDialogue,
+    transit: Transit,
+    (stops, mut stop_id, mut routes): (Vec<StopId>, Option<StopId>, Vec<RouteDirection>),
+    q: CallbackQuery,
+) -> HandlerResult {
+    log::warn!("StopRoutes:\r\n{q:#?}");
+
+    bot.answer_callback_query(q.id).await?;
+
+    let data = q.data.unwrap_or_default();
+    let bot_msg = q.message.unwrap().id;
+
+    if data == "done" {
+        let (Some(stop_id), Some(first)) = (stop_id, routes.first().cloned()) else {
+            return Ok(());
+        };
+
+        bot.edit_message_text(
+            dialogue.chat_id(),
+            bot_msg,
+            "🕗Сколько минут идти до остановки?",
+        )
+        .await?;
+
+        dialogue
+            .update(State::ReceiveLeewayTime {
+                route_id: first.route_id,
+                stop_id,
+                direction: first.direction,
+                bot_msg,
+                more_routes: routes[1..].to_vec(),
+            })
+            .await?;
+        return Ok(());
+    }
+
+    if let Some((route, route_stop)) = parse_stop_route(&data) {
+        // Forecasts come per stop, so routes of another stop start the choice anew
+        if stop_id.as_ref() != Some(&route_stop) {
+            stop_id = Some(route_stop);
+            routes = vec![route];
+        } else if routes.contains(&route) {
+            routes.retain(|chosen| chosen != &route);
+        } else {
+            routes.push(route);
+        }
+        show_stop_routes_choice(bot, dialogue, &transit, bot_msg, stops, stop_id, routes).await?;
+    }
+    Ok(())
+}
+
+async fn route_direction(
+    bot: Bot,
+    dialogue: MyDialogue,
+    transit: Transit,
+    q: CallbackQuery,
+) -> HandlerResult {
+    log::warn!("RouteDirection:\r\n{q:#?}");
+
+    bot.answer_callback_query(q.id.clone()).await?;
+
+    let bot_msg = q.message.unwrap().id;
+
+    if let Some(stop_id) = q.data.as_deref().and_then(|d| d.strip_prefix("stop:")) {
+        let stops = transit.stops_with_same_name(&stop_id.to_string()).await;
+        return show_stop_routes(bot, dialogue, &transit, bot_msg, &stops).await;
+    }
+
+    if let Some(route_id) = q.data {
+        let route_name = transit.route_name(&route_id).await?;
+        let alerts = alerts_text(&transit, std::slice::from_ref(&route_id), None).await;
+
+        let keys: Vec<Vec<InlineKeyboardButton>> = vec![vec![
+            InlineKeyboardButton::callback("➡️Туда➡️", String::from("0")),
+            InlineKeyboardButton::callback("⬅️Обратно⬅️", String::from("1")),
+        ]];
+        let keyboard = InlineKeyboardMarkup::new(keys);
+
+        bot.edit_message_text(
+            dialogue.chat_id(),
+            bot_msg,
+            format!("{route_name}{alerts}\r\nВыберите направление:"),
+        )
+        .reply_markup(keyboard)
+        .await?;
+
+        dialogue.update(State::RouteStop { route_id }).await?;
+    }
+    Ok(())
+}
+
+async fn route_stop(
+    bot: Bot,
+    dialogue: MyDialogue,
+    transit: Transit,
+    route_id: RouteId,
+    q: CallbackQuery,
+) -> HandlerResult {
+    log::warn!("RouteStop:\r\n{q:#?}");
+
+    bot.answer_callback_query(q.id).await?;
+
+    let msg = q.message.unwrap().id;
+
+    if let Some(mut direction) = q.data {
+        let mut stops = transit.stops_on_route(&route_id, &direction).await;
+        // There are circular routes that serve only in one direction (Bus 261 for example).
+        // Nevertheless, they have trip IDs for return route (that doesn't exist) and that trip IDs are not presented in `stop_times.txt`
+        // that means we will always fail when trying to get corresponding stops. So this weird workaround designed to handle this shit correctly.
+        if stops.is_err() {
+            direction = if direction == "0" {
+                "1".to_string()
+            } else {
+                "0".to_string()
+            };
+            stops = transit.stops_on_route(&route_id, &direction).await;
+        }
+
+        let mut keys: Vec<Vec<InlineKeyboardButton>> = vec![];
+
+        for id in stops.unwrap() {
+            let name = transit.stop_name(&id).await?;
+            keys.push(vec![InlineKeyboardButton::callback(name, id)]);
+        }
+        let keyboard = InlineKeyboardMarkup::new(keys);
+
+        bot.edit_message_text(dialogue.chat_id(), msg, "🚏Выберите остановку:")
+            .reply_markup(keyboard)
+            .await?;
+
+        dialogue
+            .update(State::RequestLeewayTime {
+                route_id,
+                direction,
+            })
+            .await?;
+    }
+    Ok(())
+}
+
+async fn request_leeway_time(
+    bot: Bot,
+    dialogue: MyDialogue,
+    (route_id, direction): (RouteId, String),
+    q: CallbackQuery,
+) -> HandlerResult {
+    log::warn!("RequestLeewayTime:\r\n{q:#?}");
+
+    bot.answer_callback_query(q.id).await?;
+
+    if let Some(stop_id) = q.data {
+        let bot_msg = q.message.unwrap().id;
+
+        bot.edit_message_text(
+            dialogue.chat_id(),
+            bot_msg,
+            "🕗Сколько минут идти до остановки?",
+        )
+        .await?;
+
+        dialogue
+            .update(State::ReceiveLeewayTime {
+                route_id,
+                stop_id,
+                direction,
+                bot_msg,
+                more_routes: vec![],
+            })
+            .await?;
+    }
+    Ok(())
+}
+
+async fn receive_leeway_time(
+    bot: Bot,
     dialogue: MyDialogue,
-    (route_id, stop_id, direction, bot_msg): (RouteId, StopId, String, MessageId),
+    (route_id, stop_id, direction, bot_msg, more_routes): (
+        RouteId,
+        StopId,
+        String,
+        MessageId,
+        Vec<RouteDirection>,
+    ),
     msg: Message,
 ) -> HandlerResult {
     log::warn!("ReceiveLeewayTime:\r\n{msg:#?}");
@@ -536,260 +1466,1097 @@ async fn receive_leeway_time(
                     stop_id,
                     direction,
                     leeway: leeway_minutes,
+                    more_routes,
                 })
                 .await?
         } else {
-            bot.edit_message_text(
-                dialogue.chat_id(),
-                bot_msg,
-                "🤖Мне не удалось распознать запрос. Пожалуйста, введите число",
-            )
-            .await?;
-            dialogue
-                .update(State::ReceiveLeewayTime {
-                    route_id,
-                    stop_id,
-                    direction,
-                    bot_msg,
-                })
-                .await?;
+            bot.edit_message_text(
+                dialogue.chat_id(),
+                bot_msg,
+                "🤖Мне не удалось распознать запрос. Пожалуйста, введите число",
+            )
+            .await?;
+            dialogue
+                .update(State::ReceiveLeewayTime {
+                    route_id,
+                    stop_id,
+                    direction,
+                    bot_msg,
+                    more_routes,
+                })
+                .await?;
+        }
+    }
+    bot.delete_message(dialogue.chat_id(), msg.id).await?;
+    Ok(())
+}
+
+/// The route of the dialogue followed by the other routes chosen at the stop
+fn all_routes(
+    route_id: RouteId,
+    direction: String,
+    more_routes: Vec<RouteDirection>,
+) -> Vec<RouteDirection> {
+    let mut routes = vec![RouteDirection {
+        route_id,
+        direction,
+    }];
+    routes.extend(more_routes);
+    routes
+}
+
+/// Titles of the routes, like "Автобус 🚌 3, Трамвай 🚋 3"
+async fn routes_title(transit: &Transit, routes: &[RouteDirection]) -> anyhow::Result<String> {
+    let mut titles = vec![];
+    for route in routes {
+        titles.push(transit.route_title(&route.route_id).await?);
+    }
+    Ok(titles.join(", "))
+}
+
+async fn save_query(
+    bot: Bot,
+    dialogue: MyDialogue,
+    transit: Transit,
+    (route_id, stop_id, direction, leeway, more_routes): (
+        RouteId,
+        StopId,
+        String,
+        u64,
+        Vec<RouteDirection>,
+    ),
+    q: CallbackQuery,
+) -> HandlerResult {
+    log::warn!("SaveQuery:\r\n{q:#?}");
+
+    bot.answer_callback_query(q.id).await?;
+
+    if let Some(save) = q.data {
+        let bot_msg = q.message.unwrap().id;
+
+        if save.as_str().eq("yes") {
+            bot.edit_message_text(dialogue.chat_id(), bot_msg, "🔖Введите имя для маршрута:")
+                .await?;
+            dialogue
+                .update(State::SaveQueryName {
+                    route_id,
+                    stop_id,
+                    direction,
+                    leeway,
+                    bot_msg,
+                    more_routes,
+                })
+                .await?;
+        } else {
+            let routes = all_routes(route_id, direction, more_routes);
+            let name = format!(
+                "{}, {}",
+                routes_title(&transit, &routes).await?,
+                transit.stop_name(&stop_id).await?
+            );
+            start_search(
+                bot,
+                dialogue,
+                transit,
+                bot_msg,
+                name,
+                (routes, stop_id, leeway),
+            )
+            .await?;
+        }
+    }
+
+    Ok(())
+}
+
+async fn save_query_name(
+    bot: Bot,
+    dialogue: MyDialogue,
+    transit: Transit,
+    (route_id, stop_id, direction, leeway, bot_msg, more_routes): (
+        RouteId,
+        StopId,
+        String,
+        u64,
+        MessageId,
+        Vec<RouteDirection>,
+    ),
+    msg: Message,
+) -> HandlerResult {
+    log::warn!("SaveQueryName:\r\n{msg:#?}");
+
+    if let Some(name) = msg.text() {
+        let routes = all_routes(route_id, direction, more_routes);
+        dialogue.chat_id().add_route_to_saved(
+            name.to_string(),
+            SavedRouteData {
+                city: transit.id().to_string(),
+                routes: routes.clone(),
+                stop_id: stop_id.clone(),
+                leeway,
+            },
+        )?;
+
+        start_search(
+            bot.clone(),
+            dialogue.clone(),
+            transit,
+            bot_msg,
+            name.to_string(),
+            (routes, stop_id, leeway),
+        )
+        .await?;
+    }
+    bot.delete_message(dialogue.chat_id(), msg.id).await?;
+    Ok(())
+}
+
+async fn start_search(
+    bot: Bot,
+    dialogue: MyDialogue,
+    transit: Transit,
+    bot_msg: MessageId,
+    name: String,
+    (routes, stop_id, leeway): (Vec<RouteDirection>, StopId, u64),
+) -> HandlerResult {
+    let chat_id = dialogue.chat_id();
+    if routes.is_empty() {
+        return Ok(());
+    }
+
+    if running_searches(chat_id).await >= MAX_SEARCHES {
+        let keys: Vec<Vec<InlineKeyboardButton>> = vec![vec![InlineKeyboardButton::callback(
+            "Новый поиск",
+            String::from("new"),
+        )]];
+        let keyboard = InlineKeyboardMarkup::new(keys);
+
+        bot.edit_message_text(
+            chat_id,
+            bot_msg,
+            format!("🤚Одновременно можно искать не больше {MAX_SEARCHES} маршрутов, отмените один из них: /searches"),
+        )
+        .reply_markup(keyboard)
+        .await?;
+
+        dialogue.update(State::Start { bot_msg }).await?;
+        return Ok(());
+    }
+
+    bot.edit_message_text(
+        chat_id,
+        bot_msg,
+        search_status(&transit, &name, &routes, &stop_id, "").await,
+    )
+    .reply_markup(search_keyboard())
+    .await?;
+
+    let search = SearchData {
+        city: transit.id().to_string(),
+        name,
+        routes,
+        stop_id: stop_id.clone(),
+        leeway,
+        started: Local::now().timestamp(),
+        not_before: None,
+        skip_trip: None,
+        skip_vehicle: None,
+    };
+    launch_search(bot, chat_id, transit, bot_msg, search).await;
+
+    dialogue.update(State::Search { bot_msg }).await?;
+    Ok(())
+}
+
+/// Tells the chat its search stopped on an error, so nobody waits for a reminder that won't come
+async fn report_stopped_search(
+    bot: &Bot,
+    chat_id: ChatId,
+    bot_msg: MessageId,
+    name: &str,
+    error: impl std::fmt::Display,
+) {
+    log::error!("Search of chat ID {} stopped: {error}", chat_id.0);
+    let text = format!("⛔️Поиск остановлен из-за ошибки: {error}\r\n🔖{name}");
+    let edited = bot
+        .edit_message_text(chat_id, bot_msg, &text)
+        .reply_markup(new_search_keyboard())
+        .await;
+    // The status message may be gone, the chat still has to know
+    if edited.is_err() {
+        if let Err(e) = bot
+            .send_message(chat_id, text)
+            .reply_markup(new_search_keyboard())
+            .await
+        {
+            log::error!(
+                "Failed to tell chat ID {} its search stopped: {e}",
+                chat_id.0
+            );
+        }
+    }
+}
+
+/// Starts a search of the saved route without the user asking, returns its status message.
+/// Nothing is started if the chat runs too many searches already.
+pub async fn start_scheduled_search(
+    bot: &Bot,
+    chat_id: ChatId,
+    transit: Transit,
+    name: &str,
+    route: &SavedRouteData,
+) -> anyhow::Result<Option<MessageId>> {
+    if running_searches(chat_id).await >= MAX_SEARCHES {
+        bot.send_message(
+            chat_id,
+            format!("🤚Не начал поиск по расписанию, уже идет {MAX_SEARCHES} поисков: /searches\r\n🔖{name}"),
+        )
+        .await?;
+        return Ok(None);
+    }
+
+    let bot_msg = bot
+        .send_message(
+            chat_id,
+            search_status(&transit, name, &route.routes, &route.stop_id, "").await,
+        )
+        .reply_markup(search_keyboard())
+        .await?
+        .id;
+
+    let search = SearchData {
+        city: transit.id().to_string(),
+        name: name.to_string(),
+        routes: route.routes.clone(),
+        stop_id: route.stop_id.clone(),
+        leeway: route.leeway,
+        started: Local::now().timestamp(),
+        not_before: None,
+        skip_trip: None,
+        skip_vehicle: None,
+    };
+    launch_search(bot.clone(), chat_id, transit, bot_msg, search).await;
+    Ok(Some(bot_msg))
+}
+
+async fn running_searches(chat_id: ChatId) -> usize {
+    POLL_TASKS
+        .lock()
+        .await
+        .get(&chat_id)
+        .map_or(0, |searches| searches.len())
+}
+
+/// Stores the search to survive restarts and starts it
+async fn launch_search(
+    bot: Bot,
+    chat_id: ChatId,
+    transit: Transit,
+    bot_msg: MessageId,
+    search: SearchData,
+) {
+    if let Err(e) = searches_db::save_search(chat_id, bot_msg, &search) {
+        log::error!("Failed to save search of chat ID {}: {e}", chat_id);
+    }
+    run_search(bot, chat_id, transit, bot_msg, search).await;
+}
+
+/// Polls for the search in the background until the reminder is sent or the search is cancelled
+async fn run_search(
+    bot: Bot,
+    chat_id: ChatId,
+    transit: Transit,
+    bot_msg: MessageId,
+    search: SearchData,
+) {
+    let task = look_for_transport(bot.clone(), chat_id, transit, bot_msg, search.clone());
+
+    // Locked until the search is registered, so a search finishing right away can't be left behind
+    let mut tasks = POLL_TASKS.lock().await;
+    let stopped_name = search.name.clone();
+    let handle = tokio::spawn(async move {
+        let result = task.await;
+        take_search(chat_id, bot_msg).await;
+        if let Err(e) = &result {
+            report_stopped_search(&bot, chat_id, bot_msg, &stopped_name, e).await;
+        }
+        result
+    });
+    tasks.entry(chat_id).or_default().insert(
+        bot_msg,
+        ActiveSearch {
+            data: search,
+            handle,
+        },
+    );
+}
+
+/// Removes the search from the running and the stored ones
+async fn take_search(chat_id: ChatId, bot_msg: MessageId) -> Option<ActiveSearch> {
+    if let Err(e) = searches_db::remove_search(chat_id, bot_msg) {
+        log::error!("Failed to remove search of chat ID {}: {e}", chat_id);
+    }
+
+    let mut tasks = POLL_TASKS.lock().await;
+    let searches = tasks.get_mut(&chat_id)?;
+    let search = searches.remove(&bot_msg);
+    if searches.is_empty() {
+        tasks.remove(&chat_id);
+    }
+    search
+}
+
+/// Picks up the searches that were running when the bot stopped. The ones too old to matter
+/// are dropped and their chats told so.
+async fn resume_searches(bot: Bot, cities: Cities) {
+    let searches = match searches_db::all_searches() {
+        Ok(searches) => searches,
+        Err(e) => {
+            log::error!("Failed to load stored searches: {e}");
+            return;
+        }
+    };
+    let now = Local::now().timestamp();
+
+    // Buttons under reminders this old aren't worth keeping either
+    if let Err(e) = searches_db::remove_finished_before(now - SEARCH_MAX_AGE_SEC) {
+        log::error!("Failed to remove finished searches: {e}");
+    }
+
+    for (chat_id, bot_msg, search) in searches {
+        let Some(search) = search else {
+            log::warn!("Dropping unreadable search of chat ID {}", chat_id);
+            if let Err(e) = drop_search(&bot, chat_id, bot_msg, None).await {
+                log::error!("Failed to drop search of chat ID {}: {e}", chat_id);
+            }
+            continue;
+        };
+        match resume_in(&cities, &search, now) {
+            Some(transit) => {
+                log::warn!("Resuming search {} of chat ID {}", search.name, chat_id);
+                run_search(bot.clone(), chat_id, transit, bot_msg, search).await;
+            }
+            None => {
+                log::warn!("Dropping search {} of chat ID {}", search.name, chat_id);
+                if let Err(e) = drop_search(&bot, chat_id, bot_msg, Some(&search.name)).await {
+                    log::error!("Failed to drop search of chat ID {}: {e}", chat_id);
+                }
+            }
+        }
+    }
+}
+
+/// The city to resume a stored search in, `None` if the search is too old or its city isn't
+/// served anymore
+fn resume_in(cities: &Cities, search: &SearchData, now: i64) -> Option<Transit> {
+    if now - search.started >= SEARCH_MAX_AGE_SEC {
+        return None;
+    }
+    cities
+        .all()
+        .iter()
+        .find(|city| city.id() == search.city)
+        .cloned()
+}
+
+async fn drop_search(
+    bot: &Bot,
+    chat_id: ChatId,
+    bot_msg: MessageId,
+    name: Option<&str>,
+) -> HandlerResult {
+    searches_db::remove_search(chat_id, bot_msg)?;
+
+    // The status message may be gone already
+    let _ = bot.delete_message(chat_id, bot_msg).await;
+    let mut text = String::from(
+        "⌛️Пока бот перезапускался, поиск устарел и остановлен. Запустите его заново, если он еще нужен",
+    );
+    if let Some(name) = name {
+        text += &format!("\r\n🔖{name}");
+    }
+    bot.send_message(chat_id, text)
+        .reply_markup(new_search_keyboard())
+        .await?;
+    Ok(())
+}
+
+fn search_keyboard() -> InlineKeyboardMarkup {
+    let keys: Vec<Vec<InlineKeyboardButton>> = vec![
+        vec![InlineKeyboardButton::callback(
+            "🗺Где транспорт?🗺",
+            format!("{SEARCH_PREFIX}where"),
+        )],
+        vec![InlineKeyboardButton::callback(
+            "🚫Отменить поиск🚫",
+            format!("{SEARCH_PREFIX}cancel"),
+        )],
+        vec![InlineKeyboardButton::callback(
+            "➕Искать еще маршрут",
+            format!("{SEARCH_PREFIX}new"),
+        )],
+    ];
+    InlineKeyboardMarkup::new(keys)
+}
+
+/// Under the reminder to leave
+fn reminder_keyboard() -> InlineKeyboardMarkup {
+    let keys: Vec<Vec<InlineKeyboardButton>> = vec![
+        vec![
+            InlineKeyboardButton::callback("⏭Следующий", format!("{SEARCH_PREFIX}next")),
+            InlineKeyboardButton::callback("⏱+5 мин", format!("{SEARCH_PREFIX}snooze")),
+        ],
+        vec![InlineKeyboardButton::callback(
+            "🚫Отменить",
+            format!("{SEARCH_PREFIX}dismiss"),
+        )],
+        vec![InlineKeyboardButton::callback(
+            "🆕Новый поиск🆕",
+            format!("{SEARCH_PREFIX}new"),
+        )],
+    ];
+    InlineKeyboardMarkup::new(keys)
+}
+
+/// Shown instead of a search once it's over
+fn new_search_keyboard() -> InlineKeyboardMarkup {
+    let keys: Vec<Vec<InlineKeyboardButton>> = vec![vec![InlineKeyboardButton::callback(
+        "🆕Новый поиск🆕",
+        format!("{SEARCH_PREFIX}new"),
+    )]];
+    InlineKeyboardMarkup::new(keys)
+}
+
+/// Text of the search message, `details` tell how long to wait and what the reminder relies on
+async fn search_status(
+    transit: &Transit,
+    name: &str,
+    routes: &[RouteDirection],
+    stop_id: &StopId,
+    details: &str,
+) -> String {
+    let route_ids = routes
+        .iter()
+        .map(|route| route.route_id.clone())
+        .collect::<Vec<_>>();
+    format!(
+        "✅Готово! Я пришлю напоминание перед выходом\r\n🔖{name}{details}{}",
+        alerts_text(transit, &route_ids, Some(stop_id)).await
+    )
+}
+
+/// When to leave and the next arrivals. `arrivals` are times with whether they are realtime ones
+/// and the route they are of, if it's worth telling.
+async fn countdown_text(
+    transit: &Transit,
+    now: i64,
+    leeway: i64,
+    arrivals: &[(i64, bool, String)],
+) -> String {
+    let mut text = String::new();
+    if let Some((arrival, _, _)) = arrivals.iter().find(|(t, _, _)| t - leeway * 60 > now) {
+        text += &format!(
+            "\r\n🚶Выходить через {} мин",
+            (arrival - leeway * 60 - now) / 60
+        );
+    }
+    if !arrivals.is_empty() {
+        text += "\r\nБлижайшие:";
+    }
+    for (arrival, realtime, route) in arrivals.iter().take(ARRIVALS_SHOWN) {
+        let time = transit.local_time(*arrival).await;
+        let minutes = (arrival - now) / 60;
+        if *realtime {
+            text += &format!("\r\n📡{time}{route}, через {minutes} мин");
+        } else {
+            text += &format!("\r\n🗓{time}{route}, через {minutes} мин по расписанию");
         }
     }
-    bot.delete_message(dialogue.chat_id(), msg.id).await?;
-    Ok(())
+    text
 }
 
-async fn save_query(
+/// How fresh the realtime data is, in minutes so the message doesn't change on every poll
+fn realtime_status(update: &StopUpdate) -> String {
+    let StopUpdate::Forecast(message) = update else {
+        return String::from(
+            "\r\n⚠️Данные в реальном времени недоступны, ориентируюсь по расписанию",
+        );
+    };
+    let Some(age) = gtfs::feed_age(message) else {
+        return String::new();
+    };
+    let minutes = age / 60;
+    if age > CONFIG.realtime_max_age.as_secs() {
+        format!(
+            "\r\n⚠️Данные в реальном времени устарели на {minutes} мин, ориентируюсь по расписанию"
+        )
+    } else if minutes == 0 {
+        String::from("\r\n🕒Данные обновлены меньше минуты назад")
+    } else {
+        format!("\r\n🕒Данные обновлены {minutes} мин назад")
+    }
+}
+
+fn is_search_button(q: CallbackQuery) -> bool {
+    q.data.is_some_and(|data| data.starts_with(SEARCH_PREFIX))
+}
+
+/// Buttons of searches: the status message's ones and cancelling from the list
+async fn search(
     bot: Bot,
     dialogue: MyDialogue,
-    (route_id, stop_id, direction, leeway): (RouteId, StopId, String, u64),
+    transit: Transit,
+    cities: Cities,
     q: CallbackQuery,
 ) -> HandlerResult {
-    log::warn!("SaveQuery:\r\n{q:#?}");
+    log::warn!("Search:\r\n{q:#?}");
 
     bot.answer_callback_query(q.id).await?;
 
-    if let Some(save) = q.data {
-        let bot_msg = q.message.unwrap().id;
+    let (Some(data), Some(msg)) = (q.data, q.message) else {
+        return Ok(());
+    };
+    let chat_id = dialogue.chat_id();
+    let mut args = data[SEARCH_PREFIX.len()..].split(':');
 
-        if save.as_str().eq("yes") {
-            bot.edit_message_text(dialogue.chat_id(), bot_msg, "🔖Введите имя для маршрута:")
-                .await?;
-            dialogue
-                .update(State::SaveQueryName {
-                    route_id,
-                    stop_id,
-                    direction,
-                    leeway,
-                    bot_msg,
-                })
+    match args.next() {
+        Some("where") => {
+            let search = POLL_TASKS
+                .lock()
+                .await
+                .get(&chat_id)
+                .and_then(|searches| searches.get(&msg.id))
+                .map(|search| (search.data.routes.clone(), search.data.stop_id.clone()));
+            if let Some((routes, stop_id)) = search {
+                for route in routes {
+                    show_vehicles(
+                        bot.clone(),
+                        dialogue.clone(),
+                        &transit,
+                        (route.route_id, stop_id.clone(), route.direction),
+                    )
+                    .await?;
+                }
+            }
+        }
+        Some("cancel") => match args.next().and_then(|id| id.parse().ok()) {
+            // From the list of searches
+            Some(id) => {
+                cancel_search(&bot, chat_id, MessageId(id), "⛔️Поиск отменен⛔️").await?;
+                let (text, keyboard) = searches_list(chat_id).await;
+                bot.edit_message_text(chat_id, msg.id, text)
+                    .reply_markup(keyboard)
+                    .await?;
+            }
+            None => cancel_search(&bot, chat_id, msg.id, "⛔️Поиск отменен⛔️").await?,
+        },
+        // Under the reminder: keep watching for a later vehicle in the same message
+        Some(action @ ("next" | "snooze")) => {
+            if running_searches(chat_id).await >= MAX_SEARCHES {
+                bot.send_message(
+                    chat_id,
+                    format!("🤚Одновременно можно искать не больше {MAX_SEARCHES} маршрутов, отмените один из них: /searches"),
+                )
                 .await?;
-        } else {
-            let keys: Vec<Vec<InlineKeyboardButton>> = vec![vec![InlineKeyboardButton::callback(
-                "🚫Отменить поиск🚫",
-                String::from("cancel"),
-            )]];
-            let keyboard = InlineKeyboardMarkup::new(keys);
+                return Ok(());
+            }
+            let Some(finished) = searches_db::take_finished(chat_id, msg.id)? else {
+                bot.edit_message_reply_markup(chat_id, msg.id)
+                    .reply_markup(new_search_keyboard())
+                    .await?;
+                return Ok(());
+            };
+
+            let now = Local::now().timestamp();
+            let search = if action == "next" {
+                finished.next(now)
+            } else {
+                finished.snooze(now)
+            };
+            let transit = cities.get(Some(&search.city));
 
             bot.edit_message_text(
-                dialogue.chat_id(),
-                bot_msg,
-                "✅Готово! Я пришлю напоминание перед выходом",
+                chat_id,
+                msg.id,
+                search_status(&transit, &search.name, &search.routes, &search.stop_id, "").await,
             )
-            .reply_markup(keyboard)
+            .reply_markup(search_keyboard())
             .await?;
-
-            let polling_handle = tokio::spawn(look_for_transport(
-                bot,
-                dialogue.clone(),
-                (route_id, stop_id, direction, leeway as i64, bot_msg),
-            ));
-
-            if let Some(task) = POLL_TASKS
+            launch_search(bot, chat_id, transit, msg.id, search).await;
+        }
+        Some("dismiss") => {
+            searches_db::take_finished(chat_id, msg.id)?;
+            bot.edit_message_reply_markup(chat_id, msg.id)
+                .reply_markup(new_search_keyboard())
+                .await?;
+        }
+        Some("new") => {
+            let is_running = POLL_TASKS
                 .lock()
                 .await
-                .insert(dialogue.chat_id(), polling_handle)
-            {
-                task.abort();
+                .get(&chat_id)
+                .is_some_and(|searches| searches.contains_key(&msg.id));
+            searches_db::take_finished(chat_id, msg.id)?;
+            let keyboard = main_menu(&dialogue, &transit)?;
+            // A running search keeps its message
+            if is_running {
+                bot.send_message(chat_id, "🚗Куда едем?🚙")
+                    .reply_markup(keyboard)
+                    .await?;
+            } else {
+                bot.edit_message_text(chat_id, msg.id, "🚗Куда едем?🚙")
+                    .reply_markup(keyboard)
+                    .await?;
             }
-
-            dialogue.update(State::Search { bot_msg }).await?;
+            dialogue.update(State::NewOrSaved).await?;
         }
+        _ => {}
     }
-
     Ok(())
 }
 
-async fn save_query_name(
+/// Buttons of searches started before the buttons got `SEARCH_PREFIX`. Those searches didn't
+/// survive the restart that brought the new buttons.
+async fn old_search_button(
     bot: Bot,
     dialogue: MyDialogue,
-    (route_id, stop_id, direction, leeway, bot_msg): (RouteId, StopId, String, u64, MessageId),
-    msg: Message,
+    transit: Transit,
+    q: CallbackQuery,
 ) -> HandlerResult {
-    log::warn!("SaveQueryName:\r\n{msg:#?}");
-
-    if let Some(name) = msg.text() {
-        dialogue.chat_id().add_route_to_saved(
-            name.to_string(),
-            SavedRouteData {
-                route_id: route_id.clone(),
-                stop_id: stop_id.clone(),
-                direction: direction.clone(),
-                leeway,
-            },
-        )?;
+    log::warn!("Old search button:\r\n{q:#?}");
+
+    let (Some(data), Some(msg)) = (q.data, q.message) else {
+        bot.answer_callback_query(q.id).await?;
+        return Ok(());
+    };
+    let chat_id = dialogue.chat_id();
+
+    if data == "new" {
+        bot.answer_callback_query(q.id).await?;
+        bot.edit_message_text(chat_id, msg.id, "🚗Куда едем?🚙")
+            .reply_markup(main_menu(&dialogue, &transit)?)
+            .await?;
+        dialogue.update(State::NewOrSaved).await?;
+    } else {
+        bot.answer_callback_query(q.id)
+            .text("Этот поиск уже остановлен, начните новый")
+            .await?;
+        bot.edit_message_reply_markup(chat_id, msg.id)
+            .reply_markup(new_search_keyboard())
+            .await?;
+    }
+    Ok(())
+}
 
-        let keys: Vec<Vec<InlineKeyboardButton>> = vec![vec![InlineKeyboardButton::callback(
-            "🚫Отменить поиск🚫",
-            String::from("cancel"),
-        )]];
-        let keyboard = InlineKeyboardMarkup::new(keys);
+/// Stops the search with the status message `bot_msg`
+pub async fn cancel_search(
+    bot: &Bot,
+    chat_id: ChatId,
+    bot_msg: MessageId,
+    reason: &str,
+) -> anyhow::Result<()> {
+    if let Some(search) = take_search(chat_id, bot_msg).await {
+        search.handle.abort();
 
         bot.edit_message_text(
-            dialogue.chat_id(),
+            chat_id,
             bot_msg,
-            "✅Готово! Я пришлю напоминание перед выходом",
+            format!("{reason}\r\n🔖{}", search.data.name),
         )
+        .reply_markup(new_search_keyboard())
+        .await?;
+    }
+    Ok(())
+}
+
+async fn list_searches(bot: Bot, dialogue: MyDialogue, msg: Message) -> HandlerResult {
+    bot.delete_message(dialogue.chat_id(), msg.id).await?;
+
+    let (text, keyboard) = searches_list(dialogue.chat_id()).await;
+    bot.send_message(dialogue.chat_id(), text)
         .reply_markup(keyboard)
         .await?;
+    Ok(())
+}
 
-        let polling_handle = tokio::spawn(look_for_transport(
-            bot.clone(),
-            dialogue.clone(),
-            (route_id, stop_id, direction, leeway as i64, bot_msg),
-        ));
+/// Running searches of the chat in the order they were started, with buttons to cancel them
+async fn searches_list(chat_id: ChatId) -> (String, InlineKeyboardMarkup) {
+    let tasks = POLL_TASKS.lock().await;
+    let mut searches = tasks
+        .get(&chat_id)
+        .map(|searches| searches.iter().collect::<Vec<_>>())
+        .unwrap_or_default();
+    if searches.is_empty() {
+        return (
+            String::from("🤷Активных поисков нет"),
+            InlineKeyboardMarkup::default(),
+        );
+    }
+    searches.sort_by_key(|(bot_msg, _)| bot_msg.0);
+
+    let keys: Vec<Vec<InlineKeyboardButton>> = searches
+        .iter()
+        .map(|(bot_msg, search)| {
+            vec![InlineKeyboardButton::callback(
+                format!("🚫{}", search.data.name),
+                format!("{SEARCH_PREFIX}cancel:{}", bot_msg.0),
+            )]
+        })
+        .collect();
+    (
+        String::from("🔎Активные поиски, нажмите, чтобы отменить:"),
+        InlineKeyboardMarkup::new(keys),
+    )
+}
 
-        if let Some(task) = POLL_TASKS
-            .lock()
-            .await
-            .insert(dialogue.chat_id(), polling_handle)
-        {
-            task.abort();
+/// Active service alerts for the routes, one per line, empty if there are none
+async fn alerts_text(transit: &Transit, route_ids: &[RouteId], stop_id: Option<&StopId>) -> String {
+    let mut alerts = vec![];
+    for route_id in route_ids {
+        for alert in alert_notifier::alerts_for(transit.id(), route_id, stop_id).await {
+            // An alert may be about several of the routes
+            if !alerts
+                .iter()
+                .any(|known: &ServiceAlert| known.id == alert.id)
+            {
+                alerts.push(alert);
+            }
         }
-
-        dialogue.update(State::Search { bot_msg }).await?;
     }
-    bot.delete_message(dialogue.chat_id(), msg.id).await?;
-    Ok(())
+    alerts
+        .iter()
+        .map(|alert| format!("\r\n⚠️{}", alert.header))
+        .collect()
 }
 
-async fn search(
+/// Sends where the vehicles are, with location pins of the approaching ones
+async fn show_vehicles(
     bot: Bot,
     dialogue: MyDialogue,
-    bot_msg: MessageId,
-    q: CallbackQuery,
+    transit: &Transit,
+    (route_id, stop_id, direction): (RouteId, StopId, String),
 ) -> HandlerResult {
-    log::warn!("Search:\r\n{q:#?}");
+    let vehicles = match transit
+        .vehicle_positions(&route_id, &direction, &stop_id)
+        .await
+    {
+        Ok(vehicles) => vehicles,
+        Err(e) => {
+            log::error!("Failed to get vehicle positions for route {route_id}: {e}");
+            bot.send_message(
+                dialogue.chat_id(),
+                "🤖Не удалось узнать, где сейчас транспорт",
+            )
+            .await?;
+            return Ok(());
+        }
+    };
 
-    bot.answer_callback_query(q.id).await?;
+    let approaching = vehicles
+        .iter()
+        .filter(|v| v.stops_away.is_some())
+        .take(VEHICLE_PINS_COUNT)
+        .collect::<Vec<_>>();
 
-    if let Some(str) = q.data {
-        if str == "cancel" {
-            if let Some(jh) = POLL_TASKS.lock().await.remove(&dialogue.chat_id()) {
-                jh.abort();
+    if approaching.is_empty() {
+        bot.send_message(
+            dialogue.chat_id(),
+            "🤷К вашей остановке сейчас никто не едет",
+        )
+        .await?;
+        return Ok(());
+    }
 
-                let keys: Vec<Vec<InlineKeyboardButton>> =
-                    vec![vec![InlineKeyboardButton::callback(
-                        "🆕Новый поиск🆕",
-                        String::from("new"),
-                    )]];
-                let keyboard = InlineKeyboardMarkup::new(keys);
+    let route_title = transit.route_title(&route_id).await?;
+    let mut text = format!("🗺{route_title}:");
+    for vehicle in &approaching {
+        let name = vehicle
+            .label
+            .as_ref()
+            .or(vehicle.vehicle_id.as_ref())
+            .map(|name| format!("№{name}"))
+            .unwrap_or_default();
+        let stops_away = vehicle.stops_away.unwrap_or_default();
+        let near = match &vehicle.nearest_stop {
+            Some(stop) => format!(", сейчас у остановки {}", transit.stop_name(stop).await?),
+            None => String::new(),
+        };
+        text += &format!("\r\n🚏{name} остановок до вас: {stops_away}{near}");
+    }
+    bot.send_message(dialogue.chat_id(), text).await?;
 
-                bot.edit_message_text(dialogue.chat_id(), bot_msg, "⛔️Поиск отменен⛔️")
-                    .reply_markup(keyboard)
-                    .await?;
+    for vehicle in approaching {
+        bot.send_location(
+            dialogue.chat_id(),
+            vehicle.position.lat,
+            vehicle.position.lon,
+        )
+        .await?;
+    }
+    Ok(())
+}
 
-                dialogue.update(State::Start { bot_msg }).await?;
-            }
+/// Next arrival of one of the searched routes
+struct Upcoming {
+    route_id: RouteId,
+    arrival: i64,
+    /// Predicted by realtime data, not taken from the timetable
+    realtime: bool,
+    trip_id: Option<TripId>,
+    vehicle_id: Option<String>,
+}
+
+/// Arrivals of the search still to come, earliest first. Each route goes by realtime data if
+/// the feed has some for it, by the timetable otherwise, then whichever comes first wins.
+fn upcoming_arrivals(
+    search: &SearchData,
+    forecast: &[(RouteId, Forecast)],
+    timetable: &[(RouteId, ScheduledArrival)],
+    cancellations: &[Cancellation],
+    now: i64,
+) -> Vec<Upcoming> {
+    let mut upcoming: Vec<Upcoming> = vec![];
+    for route in &search.routes {
+        let id = &route.route_id;
+        if forecast.iter().any(|(route_id, _)| route_id == id) {
+            upcoming.extend(
+                forecast
+                    .iter()
+                    .filter(|(route_id, f)| {
+                        route_id == id
+                            && search.wants(f.arrival, f.trip_id.as_ref(), f.vehicle_id.as_ref())
+                    })
+                    .map(|(route_id, f)| Upcoming {
+                        route_id: route_id.clone(),
+                        arrival: f.arrival,
+                        realtime: true,
+                        trip_id: f.trip_id.clone(),
+                        vehicle_id: f.vehicle_id.clone(),
+                    }),
+            );
+        } else {
+            upcoming.extend(
+                timetable
+                    .iter()
+                    .filter(|(route_id, a)| {
+                        route_id == id
+                            && a.time > now
+                            && search.wants(a.time, Some(&a.trip_id), None)
+                            && !cancellations.iter().any(|c| c.applies_to(a))
+                    })
+                    .map(|(route_id, a)| Upcoming {
+                        route_id: route_id.clone(),
+                        arrival: a.time,
+                        realtime: false,
+                        trip_id: Some(a.trip_id.clone()),
+                        vehicle_id: None,
+                    }),
+            );
         }
     }
-    Ok(())
+    upcoming.sort_by_key(|u| u.arrival);
+    upcoming
 }
 
 async fn look_for_transport(
     bot: Bot,
-    dialogue: MyDialogue,
-    (route_id, stop_id, direction, leeway, bot_msg): (RouteId, StopId, String, i64, MessageId),
+    chat_id: ChatId,
+    transit: Transit,
+    bot_msg: MessageId,
+    search: SearchData,
 ) -> HandlerResult {
-    let timetable = gtfs::arrival_timetable(&route_id, &direction, &stop_id).await?;
+    let SearchData {
+        name,
+        routes,
+        stop_id,
+        leeway,
+        ..
+    } = search.clone();
+    let leeway = leeway as i64;
+
+    let mut titles = HashMap::new();
+    for route in &routes {
+        titles.insert(
+            route.route_id.clone(),
+            transit.route_title(&route.route_id).await?,
+        );
+    }
+    // Arrivals are told apart by route only when there are several
+    let label = |route_id: &RouteId| match titles.get(route_id) {
+        Some(title) if routes.len() > 1 => format!(" {title}"),
+        _ => String::new(),
+    };
+    let route_ids = routes
+        .iter()
+        .map(|route| route.route_id.as_str())
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let mut timetable: Vec<(RouteId, ScheduledArrival)> = vec![];
+    for route in &routes {
+        for arrival in transit
+            .arrival_timetable(&route.route_id, &route.direction, &stop_id)
+            .await?
+        {
+            timetable.push((route.route_id.clone(), arrival));
+        }
+    }
+    timetable.retain(|(_, a)| search.wants(a.time, Some(&a.trip_id), None));
+    timetable.sort_by_key(|(_, a)| a.time);
+
+    let (mut update, mut updates) = forecast_hub::subscribe(&transit, &stop_id).await;
+    let mut shown_status = String::new();
+    let mut last_edit: Option<Instant> = None;
+    let mut cancellations: Vec<Cancellation> = vec![];
+    // Trips the user has been told about
+    let mut notified = HashSet::new();
 
     loop {
-        if let Ok(forecast) = gtfs::arrival_forecast(&route_id, &stop_id).await {
-            let waiting_list = forecast
+        if let Some(latest) = update.take() {
+            let realtime = realtime_status(&latest);
+            let mut forecast: Vec<(RouteId, Forecast)> = vec![];
+            if let StopUpdate::Forecast(message) = latest {
+                // Last known cancellations still hold while realtime is down
+                cancellations.clear();
+                for route in &routes {
+                    cancellations.extend(
+                        transit
+                            .cancellations(&message, &route.route_id, &route.direction, &stop_id)
+                            .await,
+                    );
+                    for f in transit
+                        .upcoming_arrivals(&message, &route.route_id, &route.direction, &stop_id)
+                        .await
+                    {
+                        forecast.push((route.route_id.clone(), f));
+                    }
+                }
+            }
+            let now = Local::now().timestamp();
+            let upcoming = upcoming_arrivals(&search, &forecast, &timetable, &cancellations, now);
+            let arrivals = upcoming
+                .iter()
+                .map(|u| (u.arrival, u.realtime, label(&u.route_id)))
+                .collect::<Vec<_>>();
+            let status = countdown_text(&transit, now, leeway, &arrivals).await + &realtime;
+            // Edits are throttled, Telegram limits how often a message can change
+            if status != shown_status
+                && last_edit.is_none_or(|edit| edit.elapsed() >= STATUS_EDIT_INTERVAL)
+            {
+                // The countdown is a nicety, the reminder still has to come if it can't be shown
+                match bot
+                    .edit_message_text(
+                        chat_id,
+                        bot_msg,
+                        search_status(&transit, &name, &routes, &stop_id, &status).await,
+                    )
+                    .reply_markup(search_keyboard())
+                    .await
+                {
+                    Ok(_) => shown_status = status,
+                    Err(e) => {
+                        log::warn!("Failed to update search status of chat ID {chat_id}: {e}")
+                    }
+                }
+                last_edit = Some(Instant::now());
+            }
+
+            // The trip the user would leave for by the timetable
+            let earliest = Local::now().timestamp() + (leeway * 60);
+            if let Some((route_id, arrival)) = timetable.iter().find(|(_, a)| a.time > earliest) {
+                let cancellation = cancellations.iter().find(|c| c.applies_to(arrival));
+                if let Some(cancellation) = cancellation {
+                    if notified.insert(arrival.trip_id.clone()) {
+                        let time = transit.local_time(arrival.time).await;
+                        let trip = label(route_id);
+                        let text = if cancellation.skipped {
+                            format!(
+                                "❌Рейс{trip} в {time} проедет вашу остановку, жду следующий\r\n🔖{name}"
+                            )
+                        } else {
+                            format!("❌Рейс{trip} в {time} отменен, жду следующий\r\n🔖{name}")
+                        };
+                        if let Err(e) = bot.send_message(chat_id, text).await {
+                            log::warn!(
+                                "Failed to tell chat ID {chat_id} about a cancellation: {e}"
+                            );
+                        }
+                    }
+                }
+            }
+            let now = Local::now().timestamp();
+            let waiting_list = upcoming
                 .iter()
-                .filter(|&&x| x - (leeway * 60) > 0)
-                .collect::<Vec<&i64>>();
+                .filter(|u| u.arrival - now - (leeway * 60) > 0)
+                .collect::<Vec<_>>();
             log::warn!(
-                "Chat ID {} waiting time for route {} at stop {} is {:?}",
-                dialogue.chat_id().0,
-                route_id,
+                "Chat ID {} waiting time for routes {} at stop {} is {:?}",
+                chat_id.0,
+                route_ids,
                 stop_id,
                 waiting_list
+                    .iter()
+                    .map(|u| (u.arrival - now, u.realtime))
+                    .collect::<Vec<_>>()
             );
 
-            if waiting_list.is_empty() {
-                let time = Local::now().timestamp() + (leeway * 60);
-                let next_on_timetable = timetable
-                    .iter()
-                    .filter_map(|t| if t - time > 0 { Some(t - time) } else { None })
-                    .collect::<Vec<i64>>();
-                log::warn!(
-                    "Chat ID {} waiting time by timetable for route {} at stop {} is {:?}",
-                    dialogue.chat_id().0,
-                    route_id,
-                    stop_id,
-                    next_on_timetable
-                );
-                if next_on_timetable.iter().any(|&t| t < 60) {
+            if let Some(next) = waiting_list
+                .first()
+                .filter(|u| u.arrival - now - (leeway * 60) < 60)
+            {
+                let title = titles.get(&next.route_id).cloned().unwrap_or_default();
+                let text = if next.realtime {
+                    log::warn!("Yielded by actual data");
+                    format!("⏰Пора выходить!⏰\r\n{title}\r\n🔖{name}")
+                } else {
                     log::warn!("Yielded by timetable");
-
-                    bot.delete_message(dialogue.chat_id(), bot_msg).await?;
-
-                    let keys: Vec<Vec<InlineKeyboardButton>> =
-                        vec![vec![InlineKeyboardButton::callback(
-                            "🆕Новый поиск🆕",
-                            String::from("new"),
-                        )]];
-
-                    let keyboard = InlineKeyboardMarkup::new(keys);
-
-                    let bot_msg = bot.send_message(dialogue.chat_id(), "⏰Я не нашел актуальных данных, но если верить расписанию, пора выходить!⏰").reply_markup(keyboard).await?.id;
-
-                    dialogue.update(State::Start { bot_msg }).await?;
-
-                    return Ok(());
-                }
-            } else {
-                for time in waiting_list {
-                    if let Some(time_left) = time.checked_sub(leeway * 60) {
-                        if time_left < 60 {
-                            log::warn!("Yielded by actual data");
-
-                            bot.delete_message(dialogue.chat_id(), bot_msg).await?;
-
-                            let keys: Vec<Vec<InlineKeyboardButton>> =
-                                vec![vec![InlineKeyboardButton::callback(
-                                    "🆕Новый поиск🆕",
-                                    String::from("new"),
-                                )]];
-
-                            let keyboard = InlineKeyboardMarkup::new(keys);
-
-                            let bot_msg = bot
-                                .send_message(dialogue.chat_id(), "⏰Пора выходить!⏰")
-                                .reply_markup(keyboard)
-                                .await?
-                                .id;
-
-                            dialogue.update(State::Start { bot_msg }).await?;
-                            return Ok(());
-                        }
-                    }
-                }
+                    format!("⏰Я не нашел актуальных данных, но если верить расписанию, пора выходить!⏰\r\n{title}\r\n🔖{name}")
+                };
+                let finished = FinishedSearch {
+                    search,
+                    arrival: next.arrival,
+                    trip_id: next.trip_id.clone(),
+                    vehicle_id: next.vehicle_id.clone(),
+                };
+                send_reminder(&bot, chat_id, bot_msg, text, finished).await?;
+                return Ok(());
             }
         }
-        tokio::time::sleep(Duration::from_secs(5)).await; // we'll continue polling in case of nothing found
+        // Everyone waiting at the stop gets the same poll
+        update = match updates.recv().await {
+            Ok(message) => Some(message),
+            Err(RecvError::Lagged(_)) => None,
+            Err(RecvError::Closed) => {
+                let (latest, receiver) = forecast_hub::subscribe(&transit, &stop_id).await;
+                updates = receiver;
+                latest
+            }
+        };
+    }
+}
+
+/// Replaces the search message with the reminder to leave
+async fn send_reminder(
+    bot: &Bot,
+    chat_id: ChatId,
+    bot_msg: MessageId,
+    text: String,
+    finished: FinishedSearch,
+) -> HandlerResult {
+    // The reminder matters more than the status message it replaces
+    if let Err(e) = bot.delete_message(chat_id, bot_msg).await {
+        log::warn!("Failed to delete search status of chat ID {chat_id}: {e}");
+    }
+
+    let alert_msg = bot
+        .send_message(chat_id, text)
+        .reply_markup(reminder_keyboard())
+        .await?
+        .id;
+    if let Err(e) = searches_db::save_finished(chat_id, alert_msg, &finished) {
+        log::error!("Failed to save finished search of chat ID {}: {e}", chat_id);
     }
+    Ok(())
 }
 
 async fn delete_unexpected(bot: Bot, dialogue: MyDialogue, msg: Message) -> HandlerResult {
@@ -809,3 +2576,252 @@ async fn delete_all(bot: Bot, dialogue: MyDialogue) {
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use chrono::NaiveDate;
+
+    use super::*;
+    use crate::gtfs::test_feed;
+    use crate::transit::FixtureTransit;
+
+    fn route(route_id: &str) -> RouteDirection {
+        RouteDirection {
+            route_id: route_id.to_string(),
+            direction: String::from("0"),
+        }
+    }
+
+    fn search(city: &str, started: i64) -> SearchData {
+        SearchData {
+            city: city.to_string(),
+            name: String::from("Работа"),
+            routes: vec![route("R1")],
+            stop_id: String::from("B"),
+            leeway: 5,
+            started,
+            not_before: None,
+            skip_trip: None,
+            skip_vehicle: None,
+        }
+    }
+
+    #[test]
+    fn recent_searches_of_served_cities_are_resumed() {
+        let cities = Cities::new(vec![Arc::new(FixtureTransit::new(test_feed::sample()))]);
+        let now = 10_000;
+
+        let transit = resume_in(&cities, &search("fixture", now - 60), now).unwrap();
+        assert_eq!(transit.id(), "fixture");
+        assert!(resume_in(
+            &cities,
+            &search("fixture", now - SEARCH_MAX_AGE_SEC + 1),
+            now
+        )
+        .is_some());
+
+        // Too old
+        assert!(resume_in(&cities, &search("fixture", now - SEARCH_MAX_AGE_SEC), now).is_none());
+        // The city was removed from the configuration
+        assert!(resume_in(&cities, &search("spb", now - 60), now).is_none());
+    }
+
+    #[test]
+    fn time_windows() {
+        let time = |hour, min| NaiveTime::from_hms_opt(hour, min, 0).unwrap();
+        assert_eq!(
+            parse_time_window("08:10-08:40"),
+            Some((time(8, 10), time(8, 40)))
+        );
+        assert_eq!(
+            parse_time_window(" 8:10 – 9:05 "),
+            Some((time(8, 10), time(9, 5)))
+        );
+        // Past midnight
+        assert_eq!(
+            parse_time_window("23:30-00:30"),
+            Some((time(23, 30), time(0, 30)))
+        );
+        assert_eq!(parse_time_window("08:10-08:10"), None);
+        assert_eq!(parse_time_window("08:10"), None);
+        assert_eq!(parse_time_window("8 утра-9"), None);
+    }
+
+    fn arrival(trip_id: &str, time: i64) -> ScheduledArrival {
+        ScheduledArrival {
+            trip_id: trip_id.to_string(),
+            service_date: NaiveDate::from_ymd_opt(2026, 3, 2).unwrap(),
+            time,
+        }
+    }
+
+    fn predicted(trip_id: &str, arrival: i64) -> Forecast {
+        Forecast {
+            trip_id: Some(trip_id.to_string()),
+            vehicle_id: None,
+            arrival,
+            delay: None,
+            timestamp: None,
+        }
+    }
+
+    fn times(upcoming: &[Upcoming]) -> Vec<(i64, bool, &str)> {
+        upcoming
+            .iter()
+            .map(|u| (u.arrival, u.realtime, u.route_id.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn forecast_or_timetable_arrivals() {
+        let search = search("fixture", 0);
+        let timetable = [
+            (String::from("R1"), arrival("t1", 100)),
+            (String::from("R1"), arrival("t2", 700)),
+            (String::from("R1"), arrival("t3", 1300)),
+        ];
+        let cancelled = [Cancellation {
+            trip_id: String::from("t2"),
+            start_date: None,
+            skipped: false,
+        }];
+
+        let upcoming = upcoming_arrivals(&search, &[], &timetable, &cancelled, 200);
+        assert_eq!(times(&upcoming), vec![(1300, false, "R1")]);
+        let upcoming = upcoming_arrivals(&search, &[], &timetable, &[], 200);
+        assert_eq!(
+            times(&upcoming),
+            vec![(700, false, "R1"), (1300, false, "R1")]
+        );
+
+        let forecast = [(String::from("R1"), predicted("t2", 760))];
+        let upcoming = upcoming_arrivals(&search, &forecast, &timetable, &cancelled, 200);
+        assert_eq!(times(&upcoming), vec![(760, true, "R1")]);
+    }
+
+    #[test]
+    fn whichever_route_comes_first() {
+        let search = SearchData {
+            routes: vec![route("R1"), route("R2")],
+            ..search("fixture", 0)
+        };
+        let timetable = [
+            (String::from("R1"), arrival("a1", 600)),
+            (String::from("R2"), arrival("b1", 300)),
+            (String::from("R2"), arrival("b2", 900)),
+        ];
+        // Only R1 has realtime data, R2 still goes by the timetable
+        let forecast = [(String::from("R1"), predicted("a1", 660))];
+
+        let upcoming = upcoming_arrivals(&search, &forecast, &timetable, &[], 200);
+        assert_eq!(
+            times(&upcoming),
+            vec![(300, false, "R2"), (660, true, "R1"), (900, false, "R2")]
+        );
+        assert_eq!(upcoming[0].trip_id.as_deref(), Some("b1"));
+
+        // Routes not searched for are left out
+        let other = [(String::from("R3"), predicted("c1", 250))];
+        let upcoming = upcoming_arrivals(&search, &other, &timetable, &[], 200);
+        assert_eq!(times(&upcoming)[0], (300, false, "R2"));
+        assert_eq!(upcoming.len(), 3);
+
+        // The next vehicle after the reminded one, whatever its route
+        let next = FinishedSearch {
+            search,
+            arrival: 300,
+            trip_id: Some(String::from("b1")),
+            vehicle_id: None,
+        }
+        .next(200);
+        let upcoming = upcoming_arrivals(&next, &forecast, &timetable, &[], 200);
+        assert_eq!(
+            times(&upcoming),
+            vec![(660, true, "R1"), (900, false, "R2")]
+        );
+    }
+
+    #[tokio::test]
+    async fn countdown_to_leaving() {
+        let transit: Transit = Arc::new(FixtureTransit::new(test_feed::sample()));
+        // 2026-03-02 08:00 in Berlin
+        let now = 1_772_434_800;
+
+        let text = countdown_text(
+            &transit,
+            now,
+            5,
+            &[
+                (now + 4 * 60, true, String::new()),
+                (now + 12 * 60, true, String::from(" Трамвай 🚋 1")),
+                (now + 20 * 60, false, String::new()),
+            ],
+        )
+        .await;
+        // Too late for the first one
+        assert_eq!(
+            text,
+            "\r\n🚶Выходить через 7 мин\r\nБлижайшие:\
+             \r\n📡08:04, через 4 мин\
+             \r\n📡08:12 Трамвай 🚋 1, через 12 мин\
+             \r\n🗓08:20, через 20 мин по расписанию"
+        );
+
+        let arrivals = (1..=5)
+            .map(|i| (now + i * 600, false, String::new()))
+            .collect::<Vec<_>>();
+        let text = countdown_text(&transit, now, 5, &arrivals).await;
+        assert_eq!(text.matches("по расписанию").count(), ARRIVALS_SHOWN);
+
+        assert_eq!(countdown_text(&transit, now, 5, &[]).await, "");
+    }
+
+    fn finished() -> FinishedSearch {
+        FinishedSearch {
+            search: SearchData {
+                not_before: Some(50),
+                ..search("fixture", 100)
+            },
+            arrival: 1000,
+            trip_id: Some(String::from("t1")),
+            vehicle_id: Some(String::from("v1")),
+        }
+    }
+
+    #[test]
+    fn next_vehicle_after_the_reminded_one() {
+        let search = finished().next(900);
+        assert_eq!(search.started, 900);
+        assert_eq!(search.name, "Работа");
+
+        assert!(!search.wants(1000, Some(&String::from("t2")), None));
+        assert!(search.wants(1060, Some(&String::from("t2")), None));
+        // Running late, but it's the same vehicle
+        assert!(!search.wants(1060, Some(&String::from("t1")), None));
+        assert!(!search.wants(1060, None, Some(&String::from("v1"))));
+        assert!(search.wants(1060, None, Some(&String::from("v2"))));
+    }
+
+    #[test]
+    fn snoozed_search_leaves_later() {
+        let search = finished().snooze(900);
+        assert_eq!(search.started, 900);
+        // Leeway is 5 minutes
+        let earliest = 900 + SNOOZE_SEC + 5 * 60;
+        assert_eq!(search.not_before, Some(earliest));
+
+        assert!(!search.wants(earliest, None, None));
+        // The reminded vehicle will do if it's late enough
+        assert!(search.wants(earliest + 60, Some(&String::from("t1")), None));
+        assert!(search.wants(earliest + 60, None, Some(&String::from("v1"))));
+    }
+
+    #[test]
+    fn new_search_wants_everything() {
+        let search = search("fixture", 100);
+        assert!(search.wants(0, None, None));
+        assert!(search.wants(1000, Some(&String::from("t1")), Some(&String::from("v1"))));
+    }
+}
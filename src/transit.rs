@@ -0,0 +1,245 @@
+mod city;
+mod fixture;
+
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Local};
+use convert_case::{Case, Casing};
+use gtfs_rt::FeedMessage;
+use tokio::sync::RwLockReadGuard;
+
+use crate::config::CONFIG;
+use crate::gtfs::{
+    Cancellation, Coordinates, Forecast, RouteDirection, RouteId, RouteName, RouteNumber,
+    ScheduledArrival, ServiceAlert, StaticFeed, StopId, StopName, Vehicle, VehicleOnRoute,
+};
+pub use city::CityTransit;
+pub use fixture::FixtureTransit;
+
+/// The provider handlers are given, the one of the chat's city
+pub type Transit = Arc<dyn TransitProvider>;
+
+/// Providers of every city, the default one first
+#[derive(Clone)]
+pub struct Cities(Arc<Vec<Transit>>);
+
+impl Cities {
+    pub fn new(cities: Vec<Transit>) -> Self {
+        assert!(!cities.is_empty(), "At least one city is required");
+        Self(Arc::new(cities))
+    }
+
+    pub fn all(&self) -> &[Transit] {
+        &self.0
+    }
+
+    /// The city with the ID, or the default one if it's unknown or not chosen yet
+    pub fn get(&self, id: Option<&str>) -> Transit {
+        id.and_then(|id| self.0.iter().find(|city| city.id() == id))
+            .unwrap_or(&self.0[0])
+            .clone()
+    }
+}
+
+/// Everything the bot knows about public transport. Implementors supply the static feed and raw
+/// realtime messages, the queries on top of them are shared.
+#[async_trait]
+pub trait TransitProvider: Send + Sync {
+    /// City ID stored with chats and saved routes
+    fn id(&self) -> &str;
+
+    /// City name shown to users
+    fn name(&self) -> &str;
+
+    async fn feed(&self) -> RwLockReadGuard<'_, StaticFeed>;
+
+    /// Realtime forecast of everything calling at the stop
+    async fn fetch_stop_forecast(&self, stop_id: &StopId) -> Result<FeedMessage>;
+
+    /// Realtime positions of the vehicles of the route
+    async fn fetch_vehicle_positions(&self, route_id: &RouteId) -> Result<FeedMessage>;
+
+    /// Service alerts, `None` if there is no alerts feed
+    async fn fetch_alerts(&self) -> Result<Option<FeedMessage>>;
+
+    /// Routes with the number, one per vehicle type, in keyboard order
+    async fn routes_by_number(&self, number: &str) -> Vec<(Vehicle, RouteId)> {
+        self.feed()
+            .await
+            .routes
+            .by_vehicle
+            .iter()
+            .filter_map(|(vehicle, routes)| Some((*vehicle, routes.get(number)?.id.clone())))
+            .collect()
+    }
+
+    async fn route_name(&self, route_id: &RouteId) -> Result<RouteName> {
+        let feed = self.feed().await;
+        match feed.routes.all.get(route_id) {
+            Some(name) => {
+                let mut name = name.to_uppercase();
+                name = name.replace('\"', "");
+
+                let res = name
+                    .split('-')
+                    .map(|substr| substr.to_case(Case::Title))
+                    .collect::<Vec<String>>()
+                    .join("-");
+                Ok(res)
+            }
+            None => Err(anyhow!("Can't find route by ID")),
+        }
+    }
+
+    async fn stop_name(&self, stop_id: &StopId) -> Result<StopName> {
+        let feed = self.feed().await;
+        match feed.stops.get(stop_id) {
+            Some(stop) => {
+                let mut name = stop.name.to_uppercase();
+                name = name.replace('\"', "");
+                Ok(name.to_case(Case::Title))
+            }
+            None => Err(anyhow!("failed to get stop by ID")),
+        }
+    }
+
+    /// Vehicle type and number, like "Автобус 🚌 3"
+    async fn route_title(&self, route_id: &RouteId) -> Result<String> {
+        match self.feed().await.routes.numbers.get(route_id) {
+            Some((vehicle, number)) => Ok(format!("{vehicle} {number}")),
+            None => Err(anyhow!("Can't find route by ID")),
+        }
+    }
+
+    async fn route_number(&self, route_id: &RouteId) -> Result<RouteNumber> {
+        match self.feed().await.routes.numbers.get(route_id) {
+            Some((_, number)) => Ok(number.clone()),
+            None => Err(anyhow!("Can't find route by ID")),
+        }
+    }
+
+    /// Stops around the point with distances in meters, nearest first
+    async fn nearest_stops(
+        &self,
+        point: Coordinates,
+        count: usize,
+        radius: f64,
+    ) -> Vec<(StopId, f64)> {
+        self.feed().await.stop_index.nearest(point, count, radius)
+    }
+
+    /// Stops looking like the query, one per name
+    async fn search_stops(&self, query: &str, count: usize) -> Vec<StopId> {
+        self.feed().await.search_stops(query, count)
+    }
+
+    async fn stops_with_same_name(&self, stop_id: &StopId) -> Vec<StopId> {
+        self.feed().await.stops_with_same_name(stop_id)
+    }
+
+    /// Every route and direction serving the stop
+    async fn routes_at_stop(&self, stop_id: &StopId) -> Vec<RouteDirection> {
+        self.feed()
+            .await
+            .stop_routes
+            .get(stop_id)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    async fn stops_on_route(&self, route_id: &RouteId, direction: &str) -> Result<Vec<StopId>> {
+        match self.feed().await.route_stops(route_id, direction) {
+            Some(stops) => Ok(stops.clone()),
+            None => Err(anyhow!("Couldn't find stops for this route and direction")),
+        }
+    }
+
+    async fn arrival_timetable(
+        &self,
+        route_id: &RouteId,
+        direction: &str,
+        stop_id: &StopId,
+    ) -> Result<Vec<ScheduledArrival>> {
+        let now = Local::now().timestamp();
+
+        let feed = self.feed().await;
+
+        if !feed.trips.contains_key(route_id) {
+            return Err(anyhow!("Failed to find trips for this route ID"));
+        }
+
+        // A day ahead is more than enough for anyone waiting at the stop
+        Ok(feed.arrivals(route_id, direction, stop_id, now, now + 86400))
+    }
+
+    /// Hours and minutes of the moment in the feed's timezone
+    async fn local_time(&self, timestamp: i64) -> String {
+        let tz = self.feed().await.timezone();
+        DateTime::from_timestamp(timestamp, 0)
+            .map(|t| t.with_timezone(&tz).format("%H:%M").to_string())
+            .unwrap_or_default()
+    }
+
+    /// Arrivals of `message` still to come for the route going in `direction` at the stop, earliest first
+    async fn upcoming_arrivals(
+        &self,
+        message: &FeedMessage,
+        route_id: &RouteId,
+        direction: &str,
+        stop_id: &StopId,
+    ) -> Vec<Forecast> {
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let max_age = CONFIG.realtime_max_age.as_secs();
+
+        let mut forecasts = self
+            .feed()
+            .await
+            .match_forecasts(message, route_id, direction, stop_id);
+        forecasts.retain(|forecast| forecast.is_current(timestamp, max_age));
+
+        forecasts
+    }
+
+    /// Timetable trips of the route that won't call at the stop according to `message`
+    async fn cancellations(
+        &self,
+        message: &FeedMessage,
+        route_id: &RouteId,
+        direction: &str,
+        stop_id: &StopId,
+    ) -> Vec<Cancellation> {
+        self.feed()
+            .await
+            .match_cancellations(message, route_id, direction, stop_id)
+    }
+
+    /// Where the vehicles of the route going in `direction` are relative to the stop
+    async fn vehicle_positions(
+        &self,
+        route_id: &RouteId,
+        direction: &str,
+        stop_id: &StopId,
+    ) -> Result<Vec<VehicleOnRoute>> {
+        let message = self.fetch_vehicle_positions(route_id).await?;
+
+        Ok(self
+            .feed()
+            .await
+            .vehicles_on_route(&message, route_id, direction, stop_id))
+    }
+
+    /// Service alerts currently published, `None` if there is no alerts feed
+    async fn service_alerts(&self) -> Result<Option<Vec<ServiceAlert>>> {
+        let Some(message) = self.fetch_alerts().await? else {
+            return Ok(None);
+        };
+
+        Ok(Some(self.feed().await.service_alerts(&message)))
+    }
+}
@@ -0,0 +1,67 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use gtfs_rt::FeedMessage;
+use tokio::sync::{RwLock, RwLockReadGuard};
+
+use super::TransitProvider;
+use crate::config::CityConfig;
+use crate::gtfs::{realtime, RouteId, StaticFeed, StopId};
+
+/// A configured city: its feed, kept fresh by `feed_updater`, and realtime endpoints
+pub struct CityTransit {
+    pub config: CityConfig,
+    feed: RwLock<StaticFeed>,
+}
+
+impl CityTransit {
+    pub fn new(config: CityConfig) -> Self {
+        Self {
+            config,
+            feed: RwLock::new(StaticFeed::default()),
+        }
+    }
+
+    /// Swaps the feed in, with the configured timezone if there is one
+    pub async fn set_feed(&self, mut feed: StaticFeed) {
+        if let Some(timezone) = self.config.timezone {
+            feed.timezone = Some(timezone);
+        }
+        *self.feed.write().await = feed;
+    }
+}
+
+#[async_trait]
+impl TransitProvider for CityTransit {
+    fn id(&self) -> &str {
+        &self.config.id
+    }
+
+    fn name(&self) -> &str {
+        &self.config.name
+    }
+
+    async fn feed(&self) -> RwLockReadGuard<'_, StaticFeed> {
+        self.feed.read().await
+    }
+
+    async fn fetch_stop_forecast(&self, stop_id: &StopId) -> Result<FeedMessage> {
+        match self.config.stop_forecast_url(stop_id) {
+            Some(url) => realtime::fetch(&url).await,
+            None => Err(anyhow!("No realtime forecasts in {}", self.config.id)),
+        }
+    }
+
+    async fn fetch_vehicle_positions(&self, route_id: &RouteId) -> Result<FeedMessage> {
+        match self.config.vehicle_positions_url(route_id) {
+            Some(url) => realtime::fetch(&url).await,
+            None => Err(anyhow!("No vehicle positions in {}", self.config.id)),
+        }
+    }
+
+    async fn fetch_alerts(&self) -> Result<Option<FeedMessage>> {
+        match &self.config.alerts_url {
+            Some(url) => Ok(Some(realtime::fetch(url).await?)),
+            None => Ok(None),
+        }
+    }
+}
@@ -0,0 +1,300 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use gtfs_rt::FeedMessage;
+use prost::Message;
+use tokio::sync::{RwLock, RwLockReadGuard};
+
+use super::TransitProvider;
+use crate::gtfs::{self, RouteId, StaticFeed, StopId};
+
+/// Data held in memory: a feed and recorded realtime messages. Nothing goes to the network, so
+/// the bot can be tried offline. Stops and routes without a recording get an empty message.
+pub struct FixtureTransit {
+    feed: RwLock<StaticFeed>,
+    forecasts: HashMap<StopId, FeedMessage>,
+    vehicles: HashMap<RouteId, FeedMessage>,
+    alerts: Option<FeedMessage>,
+}
+
+impl FixtureTransit {
+    pub fn new(feed: StaticFeed) -> Self {
+        Self {
+            feed: RwLock::new(feed),
+            forecasts: HashMap::new(),
+            vehicles: HashMap::new(),
+            alerts: None,
+        }
+    }
+
+    pub fn with_forecast(mut self, stop_id: StopId, message: FeedMessage) -> Self {
+        self.forecasts.insert(stop_id, message);
+        self
+    }
+
+    pub fn with_vehicles(mut self, route_id: RouteId, message: FeedMessage) -> Self {
+        self.vehicles.insert(route_id, message);
+        self
+    }
+
+    pub fn with_alerts(mut self, message: FeedMessage) -> Self {
+        self.alerts = Some(message);
+        self
+    }
+
+    /// Reads `feed.zip`, `forecasts/<stop_id>.pb`, `vehicles/<route_id>.pb` and `alerts.pb`
+    /// from the directory. Only the feed is required.
+    pub fn load(dir: &Path) -> Result<Self> {
+        let mut fixture = Self::new(gtfs::read_feed_file(&dir.join("feed.zip"))?);
+
+        for (id, message) in read_messages(&dir.join("forecasts"))? {
+            fixture = fixture.with_forecast(id, message);
+        }
+        for (id, message) in read_messages(&dir.join("vehicles"))? {
+            fixture = fixture.with_vehicles(id, message);
+        }
+        let alerts = dir.join("alerts.pb");
+        if alerts.exists() {
+            fixture = fixture.with_alerts(FeedMessage::decode(std::fs::read(alerts)?.as_slice())?);
+        }
+
+        Ok(fixture)
+    }
+}
+
+/// GTFS-RT messages of the directory by file stem
+fn read_messages(dir: &Path) -> Result<Vec<(String, FeedMessage)>> {
+    if !dir.exists() {
+        return Ok(vec![]);
+    }
+    let mut messages = vec![];
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "pb") {
+            let Some(id) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+            let message = FeedMessage::decode(std::fs::read(&path)?.as_slice())?;
+            messages.push((id.to_string(), message));
+        }
+    }
+    Ok(messages)
+}
+
+#[async_trait]
+impl TransitProvider for FixtureTransit {
+    fn id(&self) -> &str {
+        "fixture"
+    }
+
+    fn name(&self) -> &str {
+        "Тестовый город"
+    }
+
+    async fn feed(&self) -> RwLockReadGuard<'_, StaticFeed> {
+        self.feed.read().await
+    }
+
+    async fn fetch_stop_forecast(&self, stop_id: &StopId) -> Result<FeedMessage> {
+        Ok(self.forecasts.get(stop_id).cloned().unwrap_or_default())
+    }
+
+    async fn fetch_vehicle_positions(&self, route_id: &RouteId) -> Result<FeedMessage> {
+        Ok(self.vehicles.get(route_id).cloned().unwrap_or_default())
+    }
+
+    async fn fetch_alerts(&self) -> Result<Option<FeedMessage>> {
+        Ok(self.alerts.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use gtfs_rt::trip_update::{StopTimeEvent, StopTimeUpdate};
+    use gtfs_rt::{
+        Alert, EntitySelector, FeedEntity, FeedHeader, Position, TripDescriptor, TripUpdate,
+        VehiclePosition,
+    };
+
+    use super::*;
+    use crate::gtfs::{test_feed, Vehicle};
+
+    fn now() -> i64 {
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64
+    }
+
+    fn trip(trip_id: &str) -> TripDescriptor {
+        TripDescriptor {
+            trip_id: Some(trip_id.to_string()),
+            ..Default::default()
+        }
+    }
+
+    /// Trip `f1` at stop B in `minutes`, predicted `age` seconds ago
+    fn forecast(minutes: i64, age: i64) -> FeedMessage {
+        FeedMessage {
+            header: FeedHeader {
+                timestamp: Some((now() - age) as u64),
+                ..Default::default()
+            },
+            entity: vec![FeedEntity {
+                id: String::from("1"),
+                trip_update: Some(TripUpdate {
+                    trip: trip("f1"),
+                    stop_time_update: vec![StopTimeUpdate {
+                        stop_id: Some(String::from("B")),
+                        arrival: Some(StopTimeEvent {
+                            time: Some(now() + minutes * 60),
+                            ..Default::default()
+                        }),
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }],
+        }
+    }
+
+    fn vehicles() -> FeedMessage {
+        FeedMessage {
+            entity: vec![FeedEntity {
+                id: String::from("1"),
+                vehicle: Some(VehiclePosition {
+                    trip: Some(trip("f1")),
+                    position: Some(Position {
+                        latitude: 52.50,
+                        longitude: 13.40,
+                        ..Default::default()
+                    }),
+                    stop_id: Some(String::from("A")),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    fn alerts() -> FeedMessage {
+        FeedMessage {
+            entity: vec![FeedEntity {
+                id: String::from("works"),
+                alert: Some(Alert {
+                    informed_entity: vec![EntitySelector {
+                        route_id: Some(String::from("R1")),
+                        stop_id: Some(String::from("C")),
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    fn id(id: &str) -> String {
+        id.to_string()
+    }
+
+    #[tokio::test]
+    async fn static_queries() {
+        let fixture = FixtureTransit::new(test_feed::sample());
+        assert_eq!(
+            fixture.routes_by_number("1").await,
+            vec![(Vehicle::Tram, id("R1"))]
+        );
+        assert_eq!(
+            fixture.route_title(&id("R1")).await.unwrap(),
+            "Трамвай 🚋 1"
+        );
+        assert_eq!(fixture.stop_name(&id("B")).await.unwrap(), "Beta");
+        assert_eq!(
+            fixture.stops_on_route(&id("R1"), "1").await.unwrap(),
+            vec![id("D"), id("C"), id("B"), id("A")]
+        );
+        assert_eq!(fixture.routes_at_stop(&id("B")).await.len(), 2);
+        assert!(fixture.route_title(&id("R2")).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn recorded_forecasts() {
+        let fixture = FixtureTransit::new(test_feed::sample())
+            .with_forecast(id("B"), forecast(5, 0))
+            .with_forecast(id("C"), forecast(5, 3600));
+
+        let message = fixture.fetch_stop_forecast(&id("B")).await.unwrap();
+        let arrivals = fixture
+            .upcoming_arrivals(&message, &id("R1"), "0", &id("B"))
+            .await;
+        assert_eq!(arrivals.len(), 1);
+        assert_eq!(arrivals[0].trip_id.as_deref(), Some("f1"));
+        assert!(fixture
+            .upcoming_arrivals(&message, &id("R1"), "1", &id("B"))
+            .await
+            .is_empty());
+
+        // Predictions an hour old are ignored
+        let stale = fixture.fetch_stop_forecast(&id("C")).await.unwrap();
+        assert!(fixture
+            .upcoming_arrivals(&stale, &id("R1"), "0", &id("B"))
+            .await
+            .is_empty());
+
+        // Stops without a recording get an empty message
+        let empty = fixture.fetch_stop_forecast(&id("D")).await.unwrap();
+        assert!(empty.entity.is_empty());
+    }
+
+    #[tokio::test]
+    async fn recorded_vehicles_and_alerts() {
+        let fixture = FixtureTransit::new(test_feed::sample())
+            .with_vehicles(id("R1"), vehicles())
+            .with_alerts(alerts());
+
+        let vehicles = fixture
+            .vehicle_positions(&id("R1"), "0", &id("C"))
+            .await
+            .unwrap();
+        assert_eq!(vehicles.len(), 1);
+        assert_eq!(vehicles[0].stops_away, Some(2));
+
+        let alerts = fixture.service_alerts().await.unwrap().unwrap();
+        assert_eq!(alerts.len(), 1);
+        assert!(alerts[0].affects(&id("R1"), Some(&id("C"))));
+        assert!(!alerts[0].affects(&id("R1"), Some(&id("B"))));
+
+        let without = FixtureTransit::new(test_feed::sample());
+        assert!(without.service_alerts().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn loads_a_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("feed.zip"),
+            test_feed::archive(test_feed::SAMPLE),
+        )
+        .unwrap();
+        std::fs::create_dir(dir.path().join("forecasts")).unwrap();
+        std::fs::write(
+            dir.path().join("forecasts").join("B.pb"),
+            forecast(5, 0).encode_to_vec(),
+        )
+        .unwrap();
+
+        let fixture = FixtureTransit::load(dir.path()).unwrap();
+        assert_eq!(fixture.stop_name(&id("A")).await.unwrap(), "Alpha");
+        let message = fixture.fetch_stop_forecast(&id("B")).await.unwrap();
+        assert_eq!(message.entity.len(), 1);
+        assert!(fixture.fetch_alerts().await.unwrap().is_none());
+    }
+}